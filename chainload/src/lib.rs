@@ -0,0 +1,356 @@
+// The serial chainloading protocol, shared by the kernel (which receives) and pushkernel (which sends).
+//
+// 1. The kernel announces that it's waiting by sending READY.  It may also print other things, so the sender skips everything until it sees READY.
+// 2. The sender sends a Header: MAGIC, then the image size and its CRC-32, both little endian.
+// 3. The kernel answers with a Status byte.  Anything other than Status::Ok ends the transfer.
+// 4. The sender sends the image.
+// 5. The kernel checks the CRC and answers with another Status byte.  On Status::Ok it jumps to the new image.
+#![cfg_attr(not(test), no_std)]
+
+pub const READY: [u8; 3] = [0x03; 3];
+pub const MAGIC: [u8; 4] = *b"BOOT";
+// The image is sent in chunks so that the sender can report progress.
+pub const CHUNK_SIZE: usize = 1024;
+
+// Something that can move bytes between the two ends.  Both calls block until all of the bytes have been sent / received.
+pub trait Link {
+	type Error;
+	fn send(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+	fn recv(&mut self, buf: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+	Ok = b'K',
+	BadMagic = b'M',
+	TooBig = b'S',
+	BadChecksum = b'C',
+}
+impl Status {
+	pub fn from_byte(b: u8) -> Option<Self> {
+		match b {
+			b'K' => Some(Self::Ok),
+			b'M' => Some(Self::BadMagic),
+			b'S' => Some(Self::TooBig),
+			b'C' => Some(Self::BadChecksum),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error<E> {
+	Link(E),
+	BadMagic([u8; 4]),
+	TooBig { size: u32, max: u32 },
+	BadChecksum { expected: u32, actual: u32 },
+	// The other end said no
+	Rejected(Status),
+	// The other end said something that isn't a Status
+	BadResponse(u8),
+}
+
+// CRC-32 (the zlib / ethernet one), a bit at a time because we don't need it to be fast: it only has to keep up with the UART.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+impl Crc32 {
+	pub const fn new() -> Self {
+		Self(!0)
+	}
+	pub fn update(&mut self, bytes: &[u8]) {
+		for &b in bytes {
+			self.0 ^= b as u32;
+			for _ in 0..8 {
+				let mask = (self.0 & 1).wrapping_neg();
+				self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & mask);
+			}
+		}
+	}
+	pub const fn finish(&self) -> u32 {
+		!self.0
+	}
+}
+impl Default for Crc32 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+	let mut crc = Crc32::new();
+	crc.update(bytes);
+	crc.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+	pub size: u32,
+	pub checksum: u32,
+}
+impl Header {
+	pub const LEN: usize = 12;
+	pub fn for_image(image: &[u8]) -> Self {
+		Self {
+			size: image.len() as u32,
+			checksum: crc32(image),
+		}
+	}
+	pub fn to_bytes(&self) -> [u8; Self::LEN] {
+		let mut bytes = [0; Self::LEN];
+		bytes[0..4].copy_from_slice(&MAGIC);
+		bytes[4..8].copy_from_slice(&self.size.to_le_bytes());
+		bytes[8..12].copy_from_slice(&self.checksum.to_le_bytes());
+		bytes
+	}
+	pub fn from_bytes<E>(bytes: &[u8; Self::LEN]) -> Result<Self, Error<E>> {
+		let word =
+			|i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+		if bytes[0..4] != MAGIC {
+			return Err(Error::BadMagic(word(0).to_le_bytes()));
+		}
+		Ok(Self {
+			size: word(4),
+			checksum: word(8),
+		})
+	}
+}
+
+fn respond<L: Link>(link: &mut L, status: Status) -> Result<(), Error<L::Error>> {
+	link.send(&[status as u8]).map_err(Error::Link)
+}
+
+// The kernel keeps saying READY until the header starts arriving, so there might be a few extra READY bytes ahead of the response.
+fn expect_ok<L: Link>(link: &mut L) -> Result<(), Error<L::Error>> {
+	let mut response = READY;
+	while response[0] == READY[0] {
+		link.recv(&mut response[..1]).map_err(Error::Link)?;
+	}
+	match Status::from_byte(response[0]) {
+		Some(Status::Ok) => Ok(()),
+		Some(status) => Err(Error::Rejected(status)),
+		None => Err(Error::BadResponse(response[0])),
+	}
+}
+
+// Skip whatever the kernel prints until it says it's READY.
+pub fn wait_ready<L: Link>(link: &mut L) -> Result<(), Error<L::Error>> {
+	let mut seen = 0;
+	while seen < READY.len() {
+		let mut b = [0];
+		link.recv(&mut b).map_err(Error::Link)?;
+		seen = if b[0] == READY[seen] {
+			seen + 1
+		} else if b[0] == READY[0] {
+			1
+		} else {
+			0
+		};
+	}
+	Ok(())
+}
+
+// Sender side: everything after the READY.  progress gets called with the number of bytes sent so far after every chunk.
+pub fn send_image<L: Link>(
+	link: &mut L,
+	image: &[u8],
+	mut progress: impl FnMut(usize),
+) -> Result<(), Error<L::Error>> {
+	link.send(&Header::for_image(image).to_bytes())
+		.map_err(Error::Link)?;
+	expect_ok(link)?;
+	let mut sent = 0;
+	for chunk in image.chunks(CHUNK_SIZE) {
+		link.send(chunk).map_err(Error::Link)?;
+		sent += chunk.len();
+		progress(sent);
+	}
+	expect_ok(link)
+}
+
+// Receiver side: everything after the READY.  Returns how much of dest the image filled.  Every error (besides the link failing) has already been reported to the sender.
+pub fn receive_image<L: Link>(link: &mut L, dest: &mut [u8]) -> Result<usize, Error<L::Error>> {
+	let mut header = [0; Header::LEN];
+	link.recv(&mut header).map_err(Error::Link)?;
+	let header = match Header::from_bytes(&header) {
+		Ok(header) => header,
+		Err(e) => {
+			respond(link, Status::BadMagic)?;
+			return Err(e);
+		}
+	};
+	let size = header.size as usize;
+	if size > dest.len() {
+		respond(link, Status::TooBig)?;
+		return Err(Error::TooBig {
+			size: header.size,
+			max: dest.len() as u32,
+		});
+	}
+	respond(link, Status::Ok)?;
+
+	let mut crc = Crc32::new();
+	for chunk in dest[..size].chunks_mut(CHUNK_SIZE) {
+		link.recv(chunk).map_err(Error::Link)?;
+		crc.update(chunk);
+	}
+	if crc.finish() != header.checksum {
+		respond(link, Status::BadChecksum)?;
+		return Err(Error::BadChecksum {
+			expected: header.checksum,
+			actual: crc.finish(),
+		});
+	}
+	respond(link, Status::Ok)?;
+	Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{
+		sync::mpsc::{channel, Receiver, RecvError, Sender},
+		thread,
+		vec::Vec,
+	};
+
+	// One end of an in-memory serial cable.  corrupt flips the bits of the nth byte that goes through this end.
+	struct Loopback {
+		tx: Sender<u8>,
+		rx: Receiver<u8>,
+		sent: usize,
+		corrupt: Option<usize>,
+	}
+	impl Link for Loopback {
+		type Error = RecvError;
+		fn send(&mut self, bytes: &[u8]) -> Result<(), RecvError> {
+			for &b in bytes {
+				let b = if self.corrupt == Some(self.sent) {
+					!b
+				} else {
+					b
+				};
+				self.sent += 1;
+				// The other end hanging up shows up as a RecvError on our next recv
+				let _ = self.tx.send(b);
+			}
+			Ok(())
+		}
+		fn recv(&mut self, buf: &mut [u8]) -> Result<(), RecvError> {
+			for b in buf {
+				*b = self.rx.recv()?;
+			}
+			Ok(())
+		}
+	}
+	fn cable() -> (Loopback, Loopback) {
+		let (a_tx, b_rx) = channel();
+		let (b_tx, a_rx) = channel();
+		let end = |tx, rx| Loopback {
+			tx,
+			rx,
+			sent: 0,
+			corrupt: None,
+		};
+		(end(a_tx, a_rx), end(b_tx, b_rx))
+	}
+
+	type Outcome = (
+		Result<(), Error<RecvError>>,
+		Result<Vec<u8>, Error<RecvError>>,
+	);
+
+	// Run pushkernel's side on host and the kernel's side on device, with room for max bytes in the kernel.
+	fn transfer(image: Vec<u8>, max: usize, mut host: Loopback, mut device: Loopback) -> Outcome {
+		let kernel = thread::spawn(move || {
+			device.send(b"Waiting for a kernel\r\n\x03").unwrap();
+			device.send(&READY).unwrap();
+			let mut dest = vec![0; max];
+			receive_image(&mut device, &mut dest).map(|len| {
+				dest.truncate(len);
+				dest
+			})
+		});
+		let mut last_progress = 0;
+		let sent = wait_ready(&mut host)
+			.and_then(|_| send_image(&mut host, &image, |sent| last_progress = sent));
+		if sent.is_ok() {
+			assert_eq!(last_progress, image.len());
+		}
+		drop(host);
+		(sent, kernel.join().unwrap())
+	}
+
+	#[test]
+	fn check_crc32() {
+		assert_eq!(crc32(b""), 0);
+		assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+		let mut crc = Crc32::new();
+		crc.update(b"1234");
+		crc.update(b"56789");
+		assert_eq!(crc.finish(), 0xCBF4_3926);
+	}
+
+	#[test]
+	fn check_header() {
+		let header = Header {
+			size: 0x1234_5678,
+			checksum: 0xDEAD_BEEF,
+		};
+		let bytes = header.to_bytes();
+		assert_eq!(&bytes[..4], b"BOOT");
+		assert_eq!(bytes[4], 0x78);
+		assert_eq!(Header::from_bytes::<()>(&bytes), Ok(header));
+		let mut bytes = bytes;
+		bytes[0] = b'b';
+		assert_eq!(
+			Header::from_bytes::<()>(&bytes),
+			Err(Error::BadMagic(*b"bOOT"))
+		);
+	}
+
+	#[test]
+	fn check_transfer() {
+		let image: Vec<u8> = (0..5000u32).map(|i| (i * 7) as u8).collect();
+		let (host, device) = cable();
+		let (sent, received) = transfer(image.clone(), 8192, host, device);
+		assert_eq!(sent, Ok(()));
+		assert_eq!(received, Ok(image));
+	}
+
+	#[test]
+	fn check_too_big() {
+		let (host, device) = cable();
+		let (sent, received) = transfer(vec![0; 100], 64, host, device);
+		assert_eq!(sent, Err(Error::Rejected(Status::TooBig)));
+		assert_eq!(received, Err(Error::TooBig { size: 100, max: 64 }));
+	}
+
+	#[test]
+	fn check_corruption() {
+		let image: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+		let (mut host, device) = cable();
+		// Flip a byte in the middle of the image
+		host.corrupt = Some(Header::LEN + 2000);
+		let (sent, received) = transfer(image.clone(), 4096, host, device);
+		assert_eq!(sent, Err(Error::Rejected(Status::BadChecksum)));
+		let mut corrupted = image.clone();
+		corrupted[2000] = !corrupted[2000];
+		assert_eq!(
+			received,
+			Err(Error::BadChecksum {
+				expected: crc32(&image),
+				actual: crc32(&corrupted)
+			})
+		);
+	}
+
+	#[test]
+	fn check_bad_magic() {
+		let (mut host, device) = cable();
+		host.corrupt = Some(0);
+		let (sent, received) = transfer(vec![1, 2, 3], 16, host, device);
+		assert_eq!(sent, Err(Error::Rejected(Status::BadMagic)));
+		assert_eq!(received, Err(Error::BadMagic([!b'B', b'O', b'O', b'T'])));
+	}
+}
@@ -0,0 +1,107 @@
+// Send a kernel to a Pi that's running the chainloader, then show whatever it prints.
+//
+// Usage: pushkernel <serial device> [kernel image] [baud]
+use chainload::{send_image, wait_ready, Error, Link};
+use std::{
+	env,
+	fs::{self, File, OpenOptions},
+	io::{self, Read, Write},
+	process::{self, Command},
+};
+
+const DEFAULT_IMAGE: &str = "tftp-root/kernel8.img";
+const DEFAULT_BAUD: &str = "115200";
+
+struct Serial(File);
+impl Link for Serial {
+	type Error = io::Error;
+	fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+		self.0.write_all(bytes)?;
+		self.0.flush()
+	}
+	fn recv(&mut self, buf: &mut [u8]) -> io::Result<()> {
+		self.0.read_exact(buf)
+	}
+}
+
+// GNU stty takes the device with -F, but the BSDs' (and macOS's) take it with -f.
+const STTY_DEVICE: &str = if cfg!(any(
+	target_os = "macos",
+	target_os = "freebsd",
+	target_os = "openbsd",
+	target_os = "netbsd",
+	target_os = "dragonfly"
+)) {
+	"-f"
+} else {
+	"-F"
+};
+
+// There's no termios in std, so let stty put the device into raw mode.
+fn configure(device: &str, baud: &str) -> io::Result<()> {
+	let status = Command::new("stty")
+		.args(&[
+			STTY_DEVICE,
+			device,
+			baud,
+			"raw",
+			"-echo",
+			"-ixon",
+			"-ixoff",
+			"-crtscts",
+		])
+		.status()?;
+	if status.success() {
+		Ok(())
+	} else {
+		Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!("stty failed on {}: {}", device, status),
+		))
+	}
+}
+
+fn push(device: &str, image: &str, baud: &str) -> Result<Serial, Error<io::Error>> {
+	let kernel = fs::read(image).map_err(Error::Link)?;
+	configure(device, baud).map_err(Error::Link)?;
+	let port = OpenOptions::new()
+		.read(true)
+		.write(true)
+		.open(device)
+		.map_err(Error::Link)?;
+	let mut serial = Serial(port);
+
+	eprintln!("Waiting for the chainloader on {}...", device);
+	wait_ready(&mut serial)?;
+	eprintln!("Sending {} ({} bytes)", image, kernel.len());
+	send_image(&mut serial, &kernel, |sent| {
+		eprint!("\r{:>3}%", sent * 100 / kernel.len());
+	})?;
+	eprintln!("\nBooting");
+	Ok(serial)
+}
+
+fn main() {
+	let args: Vec<String> = env::args().skip(1).collect();
+	if args.is_empty() || args.len() > 3 {
+		eprintln!("Usage: pushkernel <serial device> [kernel image] [baud]");
+		process::exit(2);
+	}
+	let device = &args[0];
+	let image = args.get(1).map_or(DEFAULT_IMAGE, |s| s.as_str());
+	let baud = args.get(2).map_or(DEFAULT_BAUD, |s| s.as_str());
+
+	let mut serial = match push(device, image, baud) {
+		Ok(serial) => serial,
+		Err(e) => {
+			eprintln!("\nChainloading failed: {:?}", e);
+			process::exit(1);
+		}
+	};
+	// Act like a (read only) terminal until we're killed
+	let mut stdout = io::stdout();
+	if let Err(e) = io::copy(&mut serial.0, &mut stdout) {
+		eprintln!("Lost the serial port: {}", e);
+		process::exit(1);
+	}
+}
@@ -0,0 +1,187 @@
+// Chainloader mode: instead of running main, wait for pushkernel to send a kernel over the console UART and jump to it.  The new kernel gets loaded at 0x80000, right on top of us, so the last step runs from a copy of the trampoline below that we've moved out of the way.
+use super::{
+	cpu,
+	memory::frames::{self, FRAME_SIZE},
+	timer::{self, Duration},
+	uart::{self, ConsoleUart, SerialPort},
+};
+use chainload::{receive_image, Link, READY};
+use core::{convert::Infallible, fmt::Write, mem, ptr, slice};
+
+// Where the firmware would have loaded kernel8.img
+const LOAD_ADDRESS: usize = 0x8_0000;
+// The biggest kernel we'll take.  The receive buffer comes from the frame allocator, so it's always past our heap, and our heap is bigger than this, so the buffer can't overlap where the image goes.
+const MAX_IMAGE: usize = 8 << 20;
+const BAUD: u32 = 115200;
+// How often to say READY while nobody's sending anything
+const READY_INTERVAL: Duration = Duration::from_secs(1);
+
+struct SerialLink<'a> {
+	serial: &'a mut ConsoleUart,
+	// The byte that told us the sender had started
+	pending: Option<u8>,
+}
+impl Link for SerialLink<'_> {
+	type Error = Infallible;
+	fn send(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+		for &b in bytes {
+			self.serial.write_byte(b);
+		}
+		Ok(())
+	}
+	fn recv(&mut self, buf: &mut [u8]) -> Result<(), Infallible> {
+		for b in buf {
+			*b = match self.pending.take() {
+				Some(b) => b,
+				None => self.serial.read_byte(),
+			};
+		}
+		Ok(())
+	}
+}
+
+pub fn run() -> ! {
+	let mut serial = uart::console_uart(BAUD).unwrap();
+	let buffer = frames::allocate(MAX_IMAGE / FRAME_SIZE, 1).expect("No room for a kernel");
+	let buffer = unsafe { slice::from_raw_parts_mut(buffer as *mut u8, MAX_IMAGE) };
+	loop {
+		writeln!(&mut serial, "Chainloader: waiting for a kernel").unwrap();
+		let first = wait_for_sender(&mut serial);
+		let mut link = SerialLink {
+			serial: &mut serial,
+			pending: Some(first),
+		};
+		match receive_image(&mut link, buffer) {
+			Ok(len) => {
+				// Make sure the last Status gets out before the new kernel resets the UART
+				serial.flush();
+				unsafe { boot(&buffer[..len]) };
+			}
+			Err(e) => {
+				// Let whatever the sender had in flight go by before we start over
+				while wait_for_byte(&mut serial, Duration::from_millis(100)).is_some() {}
+				writeln!(&mut serial, "\nChainloading failed: {:?}", e).unwrap();
+			}
+		}
+	}
+}
+
+fn wait_for_byte(serial: &mut ConsoleUart, timeout: Duration) -> Option<u8> {
+	let deadline = timer::now() + timeout;
+	while timer::now() < deadline {
+		if let Some(b) = serial.try_read() {
+			return Some(b);
+		}
+	}
+	None
+}
+
+// Keep saying READY until the sender starts talking, and return the first thing it said.
+fn wait_for_sender(serial: &mut ConsoleUart) -> u8 {
+	loop {
+		for &b in READY.iter() {
+			serial.write_byte(b);
+		}
+		if let Some(b) = wait_for_byte(serial, READY_INTERVAL) {
+			return b;
+		}
+	}
+}
+
+extern "C" {
+	static chainload_trampoline: u8;
+	static chainload_trampoline_end: u8;
+}
+
+// Copy the trampoline into a frame of its own and hand it the image.  Never returns.
+unsafe fn boot(image: &[u8]) -> ! {
+	let source = image.as_ptr() as usize;
+	assert!(source >= LOAD_ADDRESS + image.len());
+
+	let start = ptr::addr_of!(chainload_trampoline);
+	let len = ptr::addr_of!(chainload_trampoline_end).offset_from(start) as usize;
+	let trampoline = frames::allocate(1, 1).expect("No room for the trampoline");
+	assert!(len <= FRAME_SIZE);
+	ptr::copy_nonoverlapping(start, trampoline as *mut u8, len);
+
+	// The trampoline runs with the caches off, so everything it reads has to be in memory.
+	cpu::clean_dcache(trampoline, len);
+	cpu::clean_dcache(source, image.len());
+	asm!("ic iallu", "dsb sy", "isb", "msr DAIFSet, #0xf");
+
+	let trampoline: extern "C" fn(usize, usize, usize) -> ! = mem::transmute(trampoline);
+	trampoline(LOAD_ADDRESS, source, image.len());
+}
+
+// trampoline(dest, source, len): Turn off the MMU and caches, copy the image to dest, and jump to it.  The new kernel starts at KERNEL_EL with everything off, same as if it had been booted by us.  This gets copied before it runs, so it has to be position independent and can't touch the stack.
+global_asm!(
+	".section .text.chainload_trampoline",
+	".balign 8",
+	".global chainload_trampoline",
+	".global chainload_trampoline_end",
+	"chainload_trampoline:",
+	"mov x20, x0",
+	"mov x21, x1",
+	"mov x22, x2",
+	concat!("mrs x0, SCTLR_", el!()),
+	"bic x0, x0, #0x1",
+	"bic x0, x0, #0x4",
+	"bic x0, x0, #0x1000",
+	concat!("msr SCTLR_", el!(), ", x0"),
+	"isb",
+	// Clean + invalidate every data / unified cache by set and way (this is the example from ARM's programmer's guide).  Otherwise lines from the old kernel would still be sitting in the caches when the new one turns them back on.
+	"mrs x0, CLIDR_EL1",
+	"and w3, w0, #0x07000000",
+	"lsr w3, w3, #23",
+	"cbz w3, 5f",
+	"mov w10, #0",
+	"mov w8, #1",
+	"1:",
+	"add w2, w10, w10, lsr #1",
+	"lsr w1, w0, w2",
+	"and w1, w1, #0x7",
+	"cmp w1, #2",
+	"b.lt 4f",
+	"msr CSSELR_EL1, x10",
+	"isb",
+	"mrs x1, CCSIDR_EL1",
+	"and w2, w1, #7",
+	"add w2, w2, #4",
+	"ubfx w4, w1, #3, #10",
+	"clz w5, w4",
+	"lsl w9, w4, w5",
+	"lsl w16, w8, w5",
+	"2:",
+	"ubfx w7, w1, #13, #15",
+	"lsl w7, w7, w2",
+	"lsl w17, w8, w2",
+	"3:",
+	"orr w11, w10, w9",
+	"orr w11, w11, w7",
+	"dc cisw, x11",
+	"subs w7, w7, w17",
+	"b.ge 3b",
+	"subs x9, x9, x16",
+	"b.ge 2b",
+	"4:",
+	"add w10, w10, #2",
+	"cmp w3, w10",
+	"dsb sy",
+	"b.gt 1b",
+	"5:",
+	// Copy the image 8 bytes at a time.  Rounding len up is fine: the buffer is bigger than the image, and the extra bytes land in the new kernel's bss.
+	"mov x0, x20",
+	"cbz x22, 7f",
+	"6:",
+	"ldr x3, [x21], #8",
+	"str x3, [x0], #8",
+	"subs x22, x22, #8",
+	"b.gt 6b",
+	"7:",
+	"dsb sy",
+	"ic iallu",
+	"dsb sy",
+	"isb",
+	"br x20",
+	"chainload_trampoline_end:",
+);
@@ -0,0 +1,193 @@
+// The console that print! / println! / eprintln! write to.  main binds it to a SerialPort once, and everyone else shares it through the lock.
+use super::{
+	sync::{SpinLock, SpinLockGuard},
+	uart::SerialPort,
+};
+use alloc::boxed::Box;
+use core::{
+	fmt::{self, Write},
+	mem::ManuallyDrop,
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+type Port = &'static mut (dyn SerialPort + Send);
+
+static CONSOLE: SpinLock<Option<Port>> = SpinLock::new(None);
+// The core that's holding the lock, or NO_OWNER
+static OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+const NO_OWNER: usize = usize::MAX;
+// How many guards the owner has taken over the one it started with (by nesting exceptions)
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(target_arch = "aarch64")]
+fn this_core() -> usize {
+	super::cpu::core_id()
+}
+#[cfg(not(target_arch = "aarch64"))]
+fn this_core() -> usize {
+	0
+}
+
+// Start sending the console to port.  This needs the heap.
+pub fn init<S: SerialPort + Send + 'static>(port: S) {
+	let port: Port = Box::leak(Box::new(port));
+	**lock().port = Some(port);
+}
+
+pub struct ConsoleGuard {
+	// Only the outermost guard drops its SpinLockGuard
+	port: ManuallyDrop<SpinLockGuard<'static, Option<Port>>>,
+}
+impl ConsoleGuard {
+	// Nothing to write to before init.  Output goes nowhere instead of panicking, because panicking prints.
+	fn port(&mut self) -> Option<&mut (dyn SerialPort + Send + 'static)> {
+		self.port.as_deref_mut()
+	}
+}
+impl Drop for ConsoleGuard {
+	fn drop(&mut self) {
+		// A nested guard leaves the lock held for the print that it interrupted.  Interrupts stay masked too, which is how they were when it took over.
+		if DEPTH.load(Ordering::Relaxed) > 0 {
+			DEPTH.fetch_sub(1, Ordering::Relaxed);
+			return;
+		}
+		OWNER.store(NO_OWNER, Ordering::Relaxed);
+		unsafe { ManuallyDrop::drop(&mut self.port) };
+	}
+}
+impl Write for ConsoleGuard {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		match self.port() {
+			Some(port) => port.write_str(s),
+			None => Ok(()),
+		}
+	}
+}
+
+// The lock masks interrupts, so if this core is already holding it, then we're in an exception that interrupted a print and waiting would deadlock.  Take it over instead: the interrupted print is going to be garbled either way.
+pub fn lock() -> ConsoleGuard {
+	let core = this_core();
+	if OWNER.load(Ordering::Relaxed) == core {
+		DEPTH.fetch_add(1, Ordering::Relaxed);
+		unsafe { CONSOLE.force_unlock() };
+	}
+	let port = CONSOLE.lock();
+	OWNER.store(core, Ordering::Relaxed);
+	ConsoleGuard {
+		port: ManuallyDrop::new(port),
+	}
+}
+
+// For the panic handler: whoever has the console (including other cores that might never let go) loses it.
+// SAFETY: Whoever was holding the lock is going to keep going as if they still had it.
+pub unsafe fn force_unlock() {
+	OWNER.store(NO_OWNER, Ordering::Relaxed);
+	DEPTH.store(0, Ordering::Relaxed);
+	CONSOLE.force_unlock();
+}
+
+// A handle to the console that can be used like any other SerialPort.  Each call takes the lock just for itself, so holding a Console (while waiting on read_line, say) doesn't keep interrupts masked.
+pub struct Console;
+impl SerialPort for Console {
+	fn write_byte(&mut self, b: u8) {
+		if let Some(port) = lock().port() {
+			port.write_byte(b);
+		}
+	}
+	fn try_read(&mut self) -> Option<u8> {
+		lock().port().and_then(|port| port.try_read())
+	}
+	fn flush(&mut self) {
+		if let Some(port) = lock().port() {
+			port.flush();
+		}
+	}
+}
+impl Write for Console {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		lock().write_str(s)
+	}
+	// Keep everything from one write! together
+	fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
+		lock().write_fmt(args)
+	}
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments<'_>) {
+	let _ = lock().write_fmt(args);
+}
+
+// Errors are flushed right away, in case we're about to go down.
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments<'_>) {
+	let mut console = lock();
+	if let Some(port) = console.port() {
+		let _ = port.write_fmt(args);
+		port.flush();
+		return;
+	}
+	// Errors from before init (like a panic while setting up memory) still need to go somewhere, unless the console UART's pins are busy
+	#[cfg(target_arch = "aarch64")]
+	if let Some(mut uart) = super::uart::ConsoleUart::try_new() {
+		let _ = uart.write_fmt(args);
+		uart.flush();
+	}
+}
+
+macro_rules! print {
+	($($arg:tt)*) => {
+		$crate::console::_print(format_args!($($arg)*))
+	};
+}
+
+macro_rules! println {
+	() => {
+		print!("\n")
+	};
+	($fmt:expr) => {
+		print!(concat!($fmt, "\n"))
+	};
+	($fmt:expr, $($arg:tt)*) => {
+		print!(concat!($fmt, "\n"), $($arg)*)
+	};
+}
+
+macro_rules! eprint {
+	($($arg:tt)*) => {
+		$crate::console::_eprint(format_args!($($arg)*))
+	};
+}
+
+macro_rules! eprintln {
+	() => {
+		eprint!("\n")
+	};
+	($fmt:expr) => {
+		eprint!(concat!($fmt, "\n"))
+	};
+	($fmt:expr, $($arg:tt)*) => {
+		eprint!(concat!($fmt, "\n"), $($arg)*)
+	};
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_nested_lock() {
+		let outer = lock();
+		// Like an exception that prints in the middle of a print
+		let inner = lock();
+		assert_eq!(DEPTH.load(Ordering::Relaxed), 1);
+		drop(inner);
+		// The interrupted print still has the console
+		assert_eq!(DEPTH.load(Ordering::Relaxed), 0);
+		assert_eq!(OWNER.load(Ordering::Relaxed), this_core());
+		drop(outer);
+		assert_eq!(OWNER.load(Ordering::Relaxed), NO_OWNER);
+		// And it was really let go
+		drop(lock());
+	}
+}
@@ -1,9 +1,50 @@
-#[derive(Debug)]
+use super::{
+	grit::{_start_secondary, halt},
+	memory::{paging, pm::*},
+	shell::{Command, CommandError},
+};
+use core::{
+	fmt::Write,
+	hint::spin_loop,
+	mem, ptr,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+extern "C" {
+	static __stack_start: u8;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExceptionLevel {
-	EL3,
-	EL2,
-	EL1,
-	EL0,
+	EL3 = 3,
+	EL2 = 2,
+	EL1 = 1,
+	EL0 = 0,
+}
+
+#[cfg(all(feature = "el1", feature = "el2"))]
+compile_error!("Only one of the el1 and el2 features can be enabled.");
+#[cfg(not(any(feature = "el1", feature = "el2")))]
+compile_error!("One of the el1 or el2 features must be enabled.");
+
+// The exception level that the kernel runs at once the boot stage has left EL3.
+#[cfg(feature = "el2")]
+pub const KERNEL_EL: ExceptionLevel = ExceptionLevel::EL2;
+#[cfg(not(feature = "el2"))]
+pub const KERNEL_EL: ExceptionLevel = ExceptionLevel::EL1;
+
+// The suffix of the system registers that belong to KERNEL_EL, for use in asm: concat!("mrs {}, ESR_", el!())
+#[cfg(feature = "el2")]
+macro_rules! el {
+	() => {
+		"EL2"
+	};
+}
+#[cfg(not(feature = "el2"))]
+macro_rules! el {
+	() => {
+		"EL1"
+	};
 }
 
 impl ExceptionLevel {
@@ -22,3 +63,224 @@ impl ExceptionLevel {
 		}
 	}
 }
+
+pub const CORE_COUNT: usize = 4;
+// Core 0 uses the stack that _start sets up at __stack_start.  The stacks for the other cores are carved out directly below it.
+pub const CORE_STACK_SIZE: usize = 0x1_0000;
+
+// The spin table lives inside the armstub (spin_cpu0 is at 0xd8).  Secondary cores sit in wfe until their 8 byte entry is non-zero and then branch to it.
+const SPIN_TABLE: *mut u64 = 0xd8 as *mut u64;
+
+// _start_secondary reads its stack pointer from here before it can run any Rust code.
+pub(crate) static mut CORE_STACK_TOP: [usize; CORE_COUNT] = [0; CORE_COUNT];
+type CoreEntry = (unsafe fn(*mut u8), *mut u8);
+static mut CORE_ENTRY: [Option<CoreEntry>; CORE_COUNT] = [None; CORE_COUNT];
+
+// The boot core is online from the start.
+static CORE_ONLINE: [AtomicBool; CORE_COUNT] = [
+	AtomicBool::new(true),
+	AtomicBool::new(false),
+	AtomicBool::new(false),
+	AtomicBool::new(false),
+];
+
+pub fn core_id() -> usize {
+	let mpidr: u64;
+	unsafe {
+		asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem));
+	}
+	(mpidr & 0b11) as usize
+}
+
+pub fn is_online(core: usize) -> bool {
+	CORE_ONLINE[core].load(Ordering::Acquire)
+}
+
+pub fn core_stack(core: usize) -> *mut u8 {
+	assert!(core < CORE_COUNT);
+	let top = unsafe { ptr::addr_of!(__stack_start) } as usize;
+	(top - core * CORE_STACK_SIZE) as *mut u8
+}
+
+// Release a secondary core from the armstub and run entry on it.  This returns once the core has reported that it's online.  If entry returns, the core goes back to sleep.
+// SAFETY: stack must be the top of CORE_STACK_SIZE bytes that nothing else is using.  core_stack(core) gives you one of those.
+pub unsafe fn start_core<F>(core: usize, entry: F, stack: *mut u8)
+where
+	F: FnOnce() + Send + 'static,
+{
+	assert!(core < CORE_COUNT && core != core_id());
+	assert!(!is_online(core), "Core {} has already been started", core);
+
+	// We don't have an allocator, so the closure gets moved onto the top of the new core's stack.
+	let align = mem::align_of::<F>().max(16);
+	let slot = ((stack as usize - mem::size_of::<F>()) & !(align - 1)) as *mut F;
+	ptr::write(slot, entry);
+	CORE_STACK_TOP[core] = slot as usize;
+	CORE_ENTRY[core] = Some((call_entry::<F>, slot as *mut u8));
+
+	ptr::write_volatile(SPIN_TABLE.add(core), _start_secondary as usize as u64);
+
+	// The secondary core runs with its MMU and caches off until it gets into run_secondary, so everything it reads before then has to be pushed out of our cache.
+	clean_dcache(SPIN_TABLE.add(core) as usize, 8);
+	clean_dcache(ptr::addr_of!(CORE_STACK_TOP[core]) as usize, 8);
+	clean_dcache(slot as usize, stack as usize - slot as usize);
+	asm!("sev");
+
+	while !is_online(core) {
+		spin_loop();
+	}
+}
+
+unsafe fn call_entry<F: FnOnce()>(data: *mut u8) {
+	let entry = ptr::read(data as *mut F);
+	entry();
+}
+
+// Called by the secondary core once it has a stack.
+pub(crate) fn run_secondary(core: usize) -> ! {
+	paging::enable();
+	let (entry, data) =
+		unsafe { CORE_ENTRY[core] }.expect("Secondary core was released without an entry");
+	CORE_ONLINE[core].store(true, Ordering::Release);
+	unsafe { entry(data) };
+	halt();
+}
+
+fn dcache_line_size() -> usize {
+	let ctr: u64;
+	unsafe {
+		asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem));
+	}
+	// DminLine is the log2 of the number of words in the smallest data cache line.
+	4 << ((ctr >> 16) & 0xF)
+}
+
+// Clean and invalidate the data cache lines covering [start, start + len) to the point of coherency, so that anyone accessing memory without our caches (other cores before their MMU is on, the GPU) sees what we wrote.
+pub fn clean_dcache(start: usize, len: usize) {
+	let line = dcache_line_size();
+	let mut addr = start & !(line - 1);
+	while addr < start + len {
+		unsafe {
+			asm!("dc civac, {}", in(reg) addr);
+		}
+		addr += line;
+	}
+	unsafe {
+		asm!("dsb sy");
+	}
+}
+
+// Invalidate the data cache lines covering [start, start + len) without writing them back, so the next read comes from memory (for what the GPU wrote).  Anything we wrote in those lines that wasn't cleaned is lost, so they shouldn't share a line with anything else.
+pub fn invalidate_dcache(start: usize, len: usize) {
+	let line = dcache_line_size();
+	let mut addr = start & !(line - 1);
+	while addr < start + len {
+		unsafe {
+			asm!("dc ivac, {}", in(reg) addr);
+		}
+		addr += line;
+	}
+	unsafe {
+		asm!("dsb sy");
+	}
+}
+
+// Have the watchdog reset the whole chip as soon as possible.
+pub fn reboot() -> ! {
+	const RSTC_WRCFG_MASK: u32 = 0x30;
+	const RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+	unsafe {
+		// The watchdog counts down in ticks of ~16us
+		ptr::write_volatile(PM_WDOG, PM_PASSWORD | 10);
+		let rstc = ptr::read_volatile(PM_RSTC) & !RSTC_WRCFG_MASK;
+		ptr::write_volatile(PM_RSTC, PM_PASSWORD | rstc | RSTC_WRCFG_FULL_RESET);
+	}
+	halt();
+}
+
+// mrs needs the register name at compile time, so sysreg can only read the ones listed here.
+macro_rules! sysregs {
+	($($name:expr),* $(,)?) => {
+		const SYSREG_NAMES: &[&str] = &[$($name),*];
+		fn read_sysreg(name: &str) -> Option<u64> {
+			$(
+				if name.eq_ignore_ascii_case($name) {
+					let value: u64;
+					unsafe { asm!(concat!("mrs {}, ", $name), out(reg) value, options(nomem, nostack)) };
+					return Some(value);
+				}
+			)*
+			None
+		}
+	};
+}
+sysregs!(
+	"CurrentEL",
+	"DAIF",
+	"NZCV",
+	"SPSel",
+	"SP_EL0",
+	"MIDR_EL1",
+	"MPIDR_EL1",
+	"CTR_EL0",
+	"CLIDR_EL1",
+	"ID_AA64MMFR0_EL1",
+	"ID_AA64PFR0_EL1",
+	"CNTFRQ_EL0",
+	"CNTPCT_EL0",
+	"CNTVCT_EL0",
+	"CNTP_CTL_EL0",
+	"CNTP_CVAL_EL0",
+	"CNTV_CTL_EL0",
+	"CNTV_CVAL_EL0",
+	concat!("SCTLR_", el!()),
+	concat!("TCR_", el!()),
+	concat!("MAIR_", el!()),
+	concat!("TTBR0_", el!()),
+	concat!("VBAR_", el!()),
+	concat!("ESR_", el!()),
+	concat!("ELR_", el!()),
+	concat!("FAR_", el!()),
+	concat!("SPSR_", el!()),
+);
+
+fn sysreg(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	match args {
+		[] => {
+			for name in SYSREG_NAMES {
+				write!(out, "{} ", name)?;
+			}
+			writeln!(out)?;
+		}
+		[name] => {
+			let value = read_sysreg(name).ok_or(CommandError::Failed(
+				"don't know that one (run sysreg on its own for the list)",
+			))?;
+			writeln!(out, "{}: {:#018x} ({:#b})", name, value, value)?;
+		}
+		_ => return Err(CommandError::Usage),
+	}
+	Ok(())
+}
+
+fn reboot_command(_: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	writeln!(out, "Rebooting")?;
+	// Give the message a chance to get out of the UART
+	crate::timer::sleep(crate::timer::Duration::from_millis(10));
+	reboot();
+}
+
+pub static COMMANDS: [Command; 2] = [
+	Command {
+		name: "sysreg",
+		args: "[name]",
+		help: "Read a system register (or list the ones we know)",
+		run: sysreg,
+	},
+	Command {
+		name: "reboot",
+		args: "",
+		help: "Reset the Pi",
+		run: reboot_command,
+	},
+];
@@ -0,0 +1,383 @@
+#![allow(dead_code)]
+
+use super::framebuffer::{Color, Framebuffer};
+use core::fmt;
+
+// 8x16, covering ASCII (generated by tools/mkfont.py).  Control characters and the rest of Latin-1 draw as a box, and anything past the font's glyphs as '?'.
+pub static DEFAULT_FONT: &[u8] = include_bytes!("font.psf");
+
+pub struct Font<'a> {
+	glyphs: &'a [u8],
+	count: usize,
+	width: usize,
+	height: usize,
+	bytes_per_glyph: usize,
+}
+
+impl<'a> Font<'a> {
+	// Parse a PSF1 or PSF2 font.  Unicode tables are ignored, so glyphs are looked up by code point.
+	pub fn parse(data: &'a [u8]) -> Option<Self> {
+		let word = |offset: usize| -> Option<usize> {
+			let bytes = data.get(offset..offset + 4)?;
+			Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+		};
+		let (start, count, width, height, bytes_per_glyph) = match data {
+			[0x36, 0x04, mode, height, ..] => {
+				let count = if mode & 1 != 0 { 512 } else { 256 };
+				(4, count, 8, *height as usize, *height as usize)
+			}
+			[0x72, 0xb5, 0x4a, 0x86, ..] => (word(8)?, word(16)?, word(28)?, word(24)?, word(20)?),
+			_ => return None,
+		};
+		let glyphs = data.get(start..start + count * bytes_per_glyph)?;
+		Some(Self {
+			glyphs,
+			count,
+			width,
+			height,
+			bytes_per_glyph,
+		})
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	fn glyph(&self, c: char) -> &[u8] {
+		let index = match c as usize {
+			index if index < self.count => index,
+			_ => '?' as usize,
+		};
+		&self.glyphs[index * self.bytes_per_glyph..(index + 1) * self.bytes_per_glyph]
+	}
+
+	pub fn is_set(&self, c: char, x: usize, y: usize) -> bool {
+		let row_bytes = (self.width + 7) / 8;
+		self.glyph(c)[y * row_bytes + x / 8] & (0x80 >> (x % 8)) != 0
+	}
+}
+
+// The VGA colours, in ANSI order.  The second half are the bright versions.
+const PALETTE: [Color; 16] = [
+	Color(0x00_00_00),
+	Color(0xAA_00_00),
+	Color(0x00_AA_00),
+	Color(0xAA_55_00),
+	Color(0x00_00_AA),
+	Color(0xAA_00_AA),
+	Color(0x00_AA_AA),
+	Color(0xAA_AA_AA),
+	Color(0x55_55_55),
+	Color(0xFF_55_55),
+	Color(0x55_FF_55),
+	Color(0xFF_FF_55),
+	Color(0x55_55_FF),
+	Color(0xFF_55_FF),
+	Color(0x55_FF_FF),
+	Color(0xFF_FF_FF),
+];
+const DEFAULT_FG: usize = 7;
+const DEFAULT_BG: usize = 0;
+const MAX_PARAMS: usize = 8;
+// The cursor is an underline this many pixels tall
+const CURSOR_HEIGHT: usize = 2;
+
+enum Escape {
+	None,
+	// Got ESC
+	Esc,
+	// Got ESC [, collecting parameters
+	Csi,
+}
+
+// A text console on a framebuffer.  It understands \n, \r, \t, backspace, and the ANSI escapes for colours (ESC [ ... m), clearing the screen (ESC [ 2 J), and moving the cursor (ESC [ row ; col H).
+pub struct FbConsole {
+	fb: Framebuffer,
+	font: Font<'static>,
+	cols: usize,
+	rows: usize,
+	col: usize,
+	row: usize,
+	fg: usize,
+	bg: usize,
+	bold: bool,
+	escape: Escape,
+	params: [u16; MAX_PARAMS],
+	param: usize,
+	// The range of pixel rows that need to be flushed
+	dirty: Option<(usize, usize)>,
+}
+
+impl FbConsole {
+	pub fn new(fb: Framebuffer) -> Self {
+		Self::with_font(fb, Font::parse(DEFAULT_FONT).unwrap())
+	}
+	pub fn with_font(fb: Framebuffer, font: Font<'static>) -> Self {
+		let mut console = Self {
+			cols: fb.width() / font.width(),
+			rows: fb.height() / font.height(),
+			fb,
+			font,
+			col: 0,
+			row: 0,
+			fg: DEFAULT_FG,
+			bg: DEFAULT_BG,
+			bold: false,
+			escape: Escape::None,
+			params: [0; MAX_PARAMS],
+			param: 0,
+			dirty: None,
+		};
+		console.clear();
+		console.toggle_cursor();
+		console.flush();
+		console
+	}
+
+	pub fn framebuffer(&self) -> &Framebuffer {
+		&self.fb
+	}
+	pub fn cols(&self) -> usize {
+		self.cols
+	}
+	pub fn rows(&self) -> usize {
+		self.rows
+	}
+
+	fn fg_color(&self) -> Color {
+		match self.fg {
+			fg if self.bold && fg < 8 => PALETTE[fg + 8],
+			fg => PALETTE[fg],
+		}
+	}
+	fn bg_color(&self) -> Color {
+		PALETTE[self.bg]
+	}
+
+	fn mark_dirty(&mut self, y: usize, height: usize) {
+		let (start, end) = self.dirty.unwrap_or((y, y + height));
+		self.dirty = Some((start.min(y), end.max(y + height)));
+	}
+	fn flush(&mut self) {
+		if let Some((start, end)) = self.dirty.take() {
+			self.fb.flush(start, end - start);
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.fb.clear(self.bg_color());
+		self.col = 0;
+		self.row = 0;
+		self.mark_dirty(0, self.fb.height());
+	}
+
+	// The cursor is drawn by inverting the bottom of its cell, so drawing it twice removes it.
+	fn toggle_cursor(&mut self) {
+		let (w, h) = (self.font.width(), self.font.height());
+		// After writing to the last column the cursor hangs off the edge until the next character wraps
+		let x = self.col.min(self.cols - 1) * w;
+		let y = self.row * h + h - CURSOR_HEIGHT;
+		for y in y..y + CURSOR_HEIGHT {
+			for x in x..x + w {
+				self.fb.invert_pixel(x, y);
+			}
+		}
+		self.mark_dirty(y, CURSOR_HEIGHT);
+	}
+
+	fn newline(&mut self) {
+		self.col = 0;
+		self.row += 1;
+		if self.row == self.rows {
+			self.fb.scroll_up(self.font.height(), self.bg_color());
+			self.row -= 1;
+			self.mark_dirty(0, self.fb.height());
+		}
+	}
+
+	fn draw(&mut self, c: char) {
+		if self.col == self.cols {
+			self.newline();
+		}
+		let (w, h) = (self.font.width(), self.font.height());
+		let (fg, bg) = (self.fg_color(), self.bg_color());
+		for y in 0..h {
+			for x in 0..w {
+				let color = if self.font.is_set(c, x, y) { fg } else { bg };
+				self.fb.set_pixel(self.col * w + x, self.row * h + y, color);
+			}
+		}
+		self.mark_dirty(self.row * h, h);
+		self.col += 1;
+	}
+
+	fn put(&mut self, c: char) {
+		match self.escape {
+			Escape::None => match c {
+				'\x1b' => self.escape = Escape::Esc,
+				'\n' => self.newline(),
+				'\r' => self.col = 0,
+				'\x08' => self.col = self.col.saturating_sub(1),
+				'\t' => self.col = ((self.col / 8 + 1) * 8).min(self.cols),
+				c => self.draw(c),
+			},
+			Escape::Esc => {
+				if c == '[' {
+					self.params = [0; MAX_PARAMS];
+					self.param = 0;
+					self.escape = Escape::Csi;
+				} else {
+					self.escape = Escape::None;
+				}
+			}
+			Escape::Csi => match c {
+				'0'..='9' => {
+					let digit = c as u16 - '0' as u16;
+					let param = &mut self.params[self.param];
+					*param = param.saturating_mul(10).saturating_add(digit);
+				}
+				';' => self.param = (self.param + 1).min(MAX_PARAMS - 1),
+				c => {
+					self.escape = Escape::None;
+					self.control(c);
+				}
+			},
+		}
+	}
+
+	// The end of an ESC [ sequence
+	fn control(&mut self, command: char) {
+		let params = self.params;
+		let params = &params[..=self.param];
+		match command {
+			'm' => {
+				for &param in params {
+					match param {
+						0 => {
+							self.fg = DEFAULT_FG;
+							self.bg = DEFAULT_BG;
+							self.bold = false;
+						}
+						1 => self.bold = true,
+						22 => self.bold = false,
+						30..=37 => self.fg = (param - 30) as usize,
+						39 => self.fg = DEFAULT_FG,
+						40..=47 => self.bg = (param - 40) as usize,
+						49 => self.bg = DEFAULT_BG,
+						90..=97 => self.fg = (param - 90 + 8) as usize,
+						100..=107 => self.bg = (param - 100 + 8) as usize,
+						_ => {}
+					}
+				}
+			}
+			'J' if params[0] == 2 => self.clear(),
+			// Rows and columns start at 1, and 0 means the same thing as 1
+			'H' => {
+				let row = params[0].max(1) as usize - 1;
+				let col = params.get(1).copied().unwrap_or(0).max(1) as usize - 1;
+				self.row = row.min(self.rows - 1);
+				self.col = col.min(self.cols - 1);
+			}
+			_ => {}
+		}
+	}
+}
+
+impl fmt::Write for FbConsole {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		self.toggle_cursor();
+		for c in s.chars() {
+			self.put(c);
+		}
+		self.toggle_cursor();
+		self.flush();
+		Ok(())
+	}
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+	use crate::mailbox::PixelOrder;
+	use core::fmt::Write;
+
+	// 10 columns x 3 rows
+	fn console(pixels: &mut Vec<u32>) -> FbConsole {
+		*pixels = vec![0; 80 * 48];
+		let fb = unsafe { Framebuffer::from_raw(pixels.as_mut_ptr(), 80, 48, 80, PixelOrder::Bgr) };
+		FbConsole::new(fb)
+	}
+
+	// Does the cell look like c, ignoring the cursor rows
+	fn cell_is(console: &FbConsole, col: usize, row: usize, c: char, fg: Color) -> bool {
+		let font = &console.font;
+		(0..font.height() - CURSOR_HEIGHT).all(|y| {
+			(0..font.width()).all(|x| {
+				let expected = if font.is_set(c, x, y) {
+					fg
+				} else {
+					PALETTE[DEFAULT_BG]
+				};
+				console.fb.pixel(col * 8 + x, row * 16 + y) == expected
+			})
+		})
+	}
+
+	#[test]
+	fn check_font() {
+		let font = Font::parse(DEFAULT_FONT).unwrap();
+		assert_eq!((font.width(), font.height(), font.count), (8, 16, 256));
+		// The top of the A
+		assert!(font.is_set('A', 3, 3) && !font.is_set('A', 2, 3));
+		assert!((0..16).all(|y| (0..8).all(|x| !font.is_set(' ', x, y))));
+		assert!(Font::parse(&[0, 1, 2, 3]).is_none());
+	}
+
+	#[test]
+	fn check_text() {
+		let mut pixels = Vec::new();
+		let mut console = console(&mut pixels);
+		write!(console, "Hi").unwrap();
+		assert!(cell_is(&console, 0, 0, 'H', PALETTE[DEFAULT_FG]));
+		assert!(cell_is(&console, 1, 0, 'i', PALETTE[DEFAULT_FG]));
+		// The cursor is in the next cell
+		assert_eq!(console.fb.pixel(16, 15), Color(0xFF_FF_FF));
+		assert_eq!(console.fb.pixel(8, 15), PALETTE[DEFAULT_BG]);
+	}
+
+	#[test]
+	fn check_wrap_and_scroll() {
+		let mut pixels = Vec::new();
+		let mut console = console(&mut pixels);
+		write!(console, "0123456789ab\nc").unwrap();
+		assert!(cell_is(&console, 9, 0, '9', PALETTE[DEFAULT_FG]));
+		assert!(cell_is(&console, 1, 1, 'b', PALETTE[DEFAULT_FG]));
+		write!(console, "\nd").unwrap();
+		assert!(cell_is(&console, 0, 0, 'a', PALETTE[DEFAULT_FG]));
+		assert!(cell_is(&console, 0, 1, 'c', PALETTE[DEFAULT_FG]));
+		assert!(cell_is(&console, 0, 2, 'd', PALETTE[DEFAULT_FG]));
+	}
+
+	#[test]
+	fn check_colours() {
+		let mut pixels = Vec::new();
+		let mut console = console(&mut pixels);
+		write!(console, "\x1b[31mR\x1b[1;32mG\x1b[0mW").unwrap();
+		assert!(cell_is(&console, 0, 0, 'R', Color(0xAA_00_00)));
+		assert!(cell_is(&console, 1, 0, 'G', Color(0x55_FF_55)));
+		assert!(cell_is(&console, 2, 0, 'W', PALETTE[DEFAULT_FG]));
+	}
+
+	#[test]
+	fn check_clear_and_move() {
+		let mut pixels = Vec::new();
+		let mut console = console(&mut pixels);
+		write!(console, "abc\x1b[2J\x1b[Hx\x1b[3;5Hy").unwrap();
+		assert!(cell_is(&console, 0, 0, 'x', PALETTE[DEFAULT_FG]));
+		assert!(cell_is(&console, 1, 0, ' ', PALETTE[DEFAULT_FG]));
+		assert!(cell_is(&console, 4, 2, 'y', PALETTE[DEFAULT_FG]));
+	}
+}
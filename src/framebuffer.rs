@@ -0,0 +1,182 @@
+#![allow(dead_code)]
+
+use super::mailbox::{MailboxError, PixelOrder};
+use core::ptr;
+
+#[cfg(target_arch = "aarch64")]
+use super::{
+	cpu,
+	mailbox::{
+		AllocateBuffer, GetPitch, Message, SetDepth, SetPhysicalSize, SetPixelOrder,
+		SetVirtualOffset, SetVirtualSize,
+	},
+};
+
+// 0xRRGGBB
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color(pub u32);
+impl Color {
+	pub const BLACK: Color = Color(0x00_00_00);
+	pub const WHITE: Color = Color(0xFF_FF_FF);
+	pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+		Self((r as u32) << 16 | (g as u32) << 8 | b as u32)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FramebufferError {
+	Mailbox(MailboxError),
+	// The firmware gave us a depth other than 32 bits per pixel
+	Depth(u32),
+	// The firmware didn't give us a buffer
+	NoBuffer,
+}
+impl From<MailboxError> for FramebufferError {
+	fn from(e: MailboxError) -> Self {
+		Self::Mailbox(e)
+	}
+}
+
+// A linear 32 bit per pixel framebuffer.
+pub struct Framebuffer {
+	base: *mut u32,
+	width: usize,
+	height: usize,
+	// Pixels per row, which can be more than width
+	stride: usize,
+	order: PixelOrder,
+}
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+	// Ask the firmware for a width x height framebuffer.
+	#[cfg(target_arch = "aarch64")]
+	pub fn allocate(width: u32, height: u32) -> Result<Self, FramebufferError> {
+		let mut message = Message::new();
+		let size = message.push(&SetPhysicalSize(width, height))?;
+		message.push(&SetVirtualSize(width, height))?;
+		message.push(&SetVirtualOffset(0, 0))?;
+		let depth = message.push(&SetDepth(32))?;
+		let order = message.push(&SetPixelOrder(PixelOrder::Rgb))?;
+		let buffer = message.push(&AllocateBuffer { alignment: 4096 })?;
+		let pitch = message.push(&GetPitch)?;
+		message.send()?;
+
+		let (width, height) = message.get(size)?;
+		let depth = message.get(depth)?;
+		if depth != 32 {
+			return Err(FramebufferError::Depth(depth));
+		}
+		let buffer = message.get(buffer)?;
+		if buffer.base == 0 {
+			return Err(FramebufferError::NoBuffer);
+		}
+		// Strip the bus address' cache alias bits to get the ARM physical address
+		let base = (buffer.base & 0x3FFF_FFFF) as usize as *mut u32;
+		Ok(unsafe {
+			Self::from_raw(
+				base,
+				width as usize,
+				height as usize,
+				message.get(pitch)? as usize / 4,
+				message.get(order)?,
+			)
+		})
+	}
+
+	// SAFETY: base has to point to stride * height pixels that nothing else is using.
+	pub unsafe fn from_raw(
+		base: *mut u32,
+		width: usize,
+		height: usize,
+		stride: usize,
+		order: PixelOrder,
+	) -> Self {
+		Self {
+			base,
+			width,
+			height,
+			stride,
+			order,
+		}
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	// In RGB order the bytes in memory are R, G, B, so the u32 is 0xBBGGRR.
+	fn raw(&self, color: Color) -> u32 {
+		match self.order {
+			PixelOrder::Bgr => color.0,
+			PixelOrder::Rgb => color.0.swap_bytes() >> 8,
+		}
+	}
+	fn row(&self, y: usize) -> *mut u32 {
+		unsafe { self.base.add(y * self.stride) }
+	}
+
+	pub fn pixel(&self, x: usize, y: usize) -> Color {
+		assert!(x < self.width && y < self.height);
+		let raw = unsafe { ptr::read_volatile(self.row(y).add(x)) };
+		match self.order {
+			PixelOrder::Bgr => Color(raw & 0xFF_FF_FF),
+			PixelOrder::Rgb => Color(raw.swap_bytes() >> 8),
+		}
+	}
+
+	pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+		if x < self.width && y < self.height {
+			unsafe { ptr::write_volatile(self.row(y).add(x), self.raw(color)) };
+		}
+	}
+
+	// Flip every bit of the pixel, which is its own undo.
+	pub fn invert_pixel(&mut self, x: usize, y: usize) {
+		if x < self.width && y < self.height {
+			unsafe {
+				let pixel = self.row(y).add(x);
+				ptr::write_volatile(pixel, !ptr::read_volatile(pixel));
+			}
+		}
+	}
+
+	pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+		let raw = self.raw(color);
+		let x_end = (x + width).min(self.width);
+		for y in y..(y + height).min(self.height) {
+			for x in x..x_end {
+				unsafe { ptr::write_volatile(self.row(y).add(x), raw) };
+			}
+		}
+	}
+
+	pub fn clear(&mut self, color: Color) {
+		self.fill_rect(0, 0, self.width, self.height, color);
+	}
+
+	// Move everything up by rows pixel rows, and fill the rows that open up at the bottom.
+	pub fn scroll_up(&mut self, rows: usize, fill: Color) {
+		let rows = rows.min(self.height);
+		unsafe {
+			ptr::copy(
+				self.row(rows),
+				self.row(0),
+				(self.height - rows) * self.stride,
+			)
+		};
+		self.fill_rect(0, self.height - rows, self.width, rows, fill);
+	}
+
+	// The framebuffer is in cacheable memory, so what we draw has to be pushed out of the cache before the GPU sees it.
+	pub fn flush(&self, y: usize, height: usize) {
+		let height = height.min(self.height.saturating_sub(y));
+		#[cfg(target_arch = "aarch64")]
+		cpu::clean_dcache(self.row(y) as usize, height * self.stride * 4);
+		#[cfg(not(target_arch = "aarch64"))]
+		let _ = height;
+	}
+}
@@ -0,0 +1,114 @@
+#![allow(dead_code)]
+
+// The ARM generic timers.  Every core has its own set of these, all counting off of the same system counter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timer {
+	// CNTPS_*_EL1: Only accessible from secure EL1 and EL3, so not from the kernel once it has left EL3
+	SecurePhysical = 0,
+	// CNTP_*_EL0: The non-secure EL1 physical timer
+	Physical = 1,
+	// CNTHP_*_EL2: Only accessible from EL2
+	Hypervisor = 2,
+	// CNTV_*_EL0: Compares against the virtual count (physical count - CNTVOFF_EL2, which the boot stage zeroes)
+	Virtual = 3,
+}
+
+macro_rules! read_timer_reg {
+	($timer:expr, $reg:literal) => {{
+		let v: u64;
+		unsafe {
+			match $timer {
+				Timer::SecurePhysical => asm!(concat!("mrs {}, CNTPS_", $reg, "_EL1"), out(reg) v),
+				Timer::Physical => asm!(concat!("mrs {}, CNTP_", $reg, "_EL0"), out(reg) v),
+				Timer::Hypervisor => asm!(concat!("mrs {}, CNTHP_", $reg, "_EL2"), out(reg) v),
+				Timer::Virtual => asm!(concat!("mrs {}, CNTV_", $reg, "_EL0"), out(reg) v),
+			}
+		}
+		v
+	}};
+}
+macro_rules! write_timer_reg {
+	($timer:expr, $reg:literal, $v:expr) => {{
+		let v: u64 = $v;
+		unsafe {
+			match $timer {
+				Timer::SecurePhysical => asm!(concat!("msr CNTPS_", $reg, "_EL1, {}"), in(reg) v),
+				Timer::Physical => asm!(concat!("msr CNTP_", $reg, "_EL0, {}"), in(reg) v),
+				Timer::Hypervisor => asm!(concat!("msr CNTHP_", $reg, "_EL2, {}"), in(reg) v),
+				Timer::Virtual => asm!(concat!("msr CNTV_", $reg, "_EL0, {}"), in(reg) v),
+			}
+			asm!("isb");
+		}
+	}};
+}
+
+const CTL_ENABLE: u64 = 1 << 0;
+const CTL_IMASK: u64 = 1 << 1;
+const CTL_ISTATUS: u64 = 1 << 2;
+
+// Ticks per second of the system counter (19.2MHz, set by the armstub)
+pub fn frequency() -> u64 {
+	let f: u64;
+	unsafe {
+		asm!("mrs {}, CNTFRQ_EL0", out(reg) f, options(nomem));
+	}
+	f
+}
+
+pub fn physical_count() -> u64 {
+	let c: u64;
+	unsafe {
+		// The isb stops the read from happening early
+		asm!("isb", "mrs {}, CNTPCT_EL0", out(reg) c);
+	}
+	c
+}
+
+pub fn virtual_count() -> u64 {
+	let c: u64;
+	unsafe {
+		asm!("isb", "mrs {}, CNTVCT_EL0", out(reg) c);
+	}
+	c
+}
+
+impl Timer {
+	pub fn count(self) -> u64 {
+		match self {
+			Self::Virtual => virtual_count(),
+			_ => physical_count(),
+		}
+	}
+	// Fire when count() reaches cval
+	pub fn set_cval(self, cval: u64) {
+		write_timer_reg!(self, "CVAL", cval);
+	}
+	pub fn cval(self) -> u64 {
+		read_timer_reg!(self, "CVAL")
+	}
+	// Fire ticks from now.  This is just a relative way of setting CVAL.
+	pub fn set_tval(self, ticks: u32) {
+		write_timer_reg!(self, "TVAL", ticks as u64);
+	}
+	// Negative once the timer has fired
+	pub fn tval(self) -> i32 {
+		read_timer_reg!(self, "TVAL") as i32
+	}
+	pub fn enable(self) {
+		write_timer_reg!(self, "CTL", CTL_ENABLE);
+	}
+	pub fn disable(self) {
+		write_timer_reg!(self, "CTL", 0);
+	}
+	// Keep the timer running but stop it from raising its interrupt
+	pub fn mask(self) {
+		write_timer_reg!(self, "CTL", CTL_ENABLE | CTL_IMASK);
+	}
+	pub fn is_enabled(self) -> bool {
+		read_timer_reg!(self, "CTL") & CTL_ENABLE != 0
+	}
+	// ISTATUS: The timer condition has been met (whether or not the interrupt is masked)
+	pub fn fired(self) -> bool {
+		read_timer_reg!(self, "CTL") & CTL_ISTATUS != 0
+	}
+}
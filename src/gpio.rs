@@ -1,9 +1,26 @@
+#![allow(dead_code)]
+
 use super::register::{ReadOnly, ReadWrite, RegField, Shared, WriteOnly};
-use core::sync::atomic::AtomicU32;
+use super::shell::{parse_number, Command, CommandError};
+use super::{delay, sync::SpinLock};
+use core::{
+	fmt::{self, Write},
+	panic::Location,
+	ptr,
+	sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering},
+};
 
 use super::memory::gpio::*;
 
+mod bank;
+mod events;
+mod pin;
+pub use bank::{GpioBank, PinSet};
+pub use events::{EventHandler, Trigger};
+pub use pin::{Alt, Alt0, Alt1, Alt2, Alt3, Alt4, Alt5, AltFunction, Input, Output, Pin};
+
 #[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Func {
 	Input,
 	Output,
@@ -29,18 +46,111 @@ impl Func {
 	}
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pull {
+	None,
+	Down,
+	Up,
+}
+impl Pull {
+	fn val(&self) -> u32 {
+		match self {
+			Pull::None => 0b00,
+			Pull::Down => 0b01,
+			Pull::Up => 0b10,
+		}
+	}
+}
+
+// The pull control signal is shared by every pin, so only one pin can go through the sequence at a time
+static PULL_LOCK: SpinLock<()> = SpinLock::new(());
+// Each GPFSEL register has the functions of 10 pins, so changing one pin's is a read-modify-write that has to keep out everyone else's (Gpio::configure and GpioBank::configure).  It's a lock instead of a Shared RegField because exclusive loads / stores don't work on device memory.
+static FSEL_LOCK: SpinLock<()> = SpinLock::new(());
+
+// BCM2837 has 54 gpio pins in two banks.  Not all are accessible through the header.
+pub const PIN_COUNT: u8 = 54;
+
+// Bit n is set while someone has pin n (as a Gpio, or in a GpioBank).  54 pins fit in one word, so claiming any number of pins is a single atomic update.
+static OWNED: AtomicU64 = AtomicU64::new(0);
+// Where each pin was taken, for the report.  Null if the pin is free.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HOLDER: AtomicPtr<Location<'static>> = AtomicPtr::new(ptr::null_mut());
+static HOLDERS: [AtomicPtr<Location<'static>>; PIN_COUNT as usize] =
+	[NO_HOLDER; PIN_COUNT as usize];
+
+fn pins_in(mask: u64) -> impl Iterator<Item = u8> {
+	(0..PIN_COUNT).filter(move |pin| mask & (1 << pin) != 0)
+}
+
+// Try to claim every pin in mask for caller.  Either all of them are claimed, or (if someone else has any of them) none are.
+fn claim(mask: u64, caller: &'static Location<'static>) -> bool {
+	let claimed = OWNED
+		.fetch_update(Ordering::Acquire, Ordering::Relaxed, |owned| {
+			if owned & mask == 0 {
+				Some(owned | mask)
+			} else {
+				None
+			}
+		})
+		.map_err(|owned| {
+			let pin = (owned & mask).trailing_zeros() as u8;
+			match holder(pin) {
+				Some(location) => warn!("{} wanted GPIO {}, but {} has it", caller, pin, location),
+				None => warn!("{} wanted GPIO {}, but it's taken", caller, pin),
+			}
+		})
+		.is_ok();
+	if claimed {
+		for pin in pins_in(mask) {
+			HOLDERS[pin as usize].store(caller as *const _ as *mut _, Ordering::Relaxed);
+		}
+	}
+	claimed
+}
+fn release(mask: u64) {
+	for pin in pins_in(mask) {
+		HOLDERS[pin as usize].store(ptr::null_mut(), Ordering::Relaxed);
+	}
+	OWNED.fetch_and(!mask, Ordering::Release);
+}
+
+fn holder(pin: u8) -> Option<&'static Location<'static>> {
+	unsafe { HOLDERS[pin as usize].load(Ordering::Relaxed).as_ref() }
+}
+
+// List the pins that are taken and who took them
+pub fn write_holders(out: &mut dyn Write) -> fmt::Result {
+	let owned = OWNED.load(Ordering::Relaxed);
+	for pin in 0..PIN_COUNT {
+		if owned & (1 << pin) != 0 {
+			match holder(pin) {
+				Some(location) => writeln!(out, "GPIO {}: {}", pin, location)?,
+				// Taken, but the location isn't stored yet
+				None => writeln!(out, "GPIO {}: ?", pin)?,
+			}
+		}
+	}
+	Ok(())
+}
+
+// Only one Gpio can exist per pin: take returns None while some other Gpio (or GpioBank) has it, and dropping the Gpio gives the pin back.  Drivers that keep their pins for good (like the UARTs) can mem::forget them.
 pub struct Gpio {
 	pin: u8,
 }
 impl Gpio {
-	// TODO: to make gpio pins accessible from multiple cores, we need to guard
-	pub fn new(pin: u8) -> Self {
-		// BCM2837 has 54 gpio pins in two banks.  Not all are accessible through the header.
-		assert!(pin < 54);
-		Self { pin }
-	}
-	// TODO: make gpfsel Shared instead of ReadWrite
-	// SAFETY: These RegFields should be safe as long as there is only one Gpio struct active per GPIO pin at a time.  I intend to add an atomic bitset to check this on creation, and release the gpio on drop.
+	#[track_caller]
+	pub fn take(pin: u8) -> Option<Self> {
+		assert!(pin < PIN_COUNT);
+		if claim(1 << pin, Location::caller()) {
+			Some(Self { pin })
+		} else {
+			None
+		}
+	}
+	pub fn pin(&self) -> u8 {
+		self.pin
+	}
+	// SAFETY: These RegFields are safe as long as there is only one Gpio struct active per GPIO pin at a time, which take makes sure of.
 	const fn gpfsel(pin: u8) -> RegField<ReadWrite> {
 		let fsel = unsafe { GPIO_BASE.offset(pin as isize / 10) as *mut u32 };
 		let offset = (pin as u32 % 10) * 3;
@@ -56,14 +166,23 @@ impl Gpio {
 		let offset = pin as u32 % 32;
 		unsafe { RegField::new(WriteOnly(gpclr), 1, offset) }
 	}
-	#[allow(unused)]
 	const fn gplev(pin: u8) -> RegField<ReadOnly> {
 		let gplev = unsafe { GPIO_BASE.offset(13 + pin as isize / 32) as *const u32 };
 		let offset = pin as u32 % 32;
 		unsafe { RegField::new(ReadOnly(gplev), 1, offset) }
 	}
+	const fn gppud() -> RegField<WriteOnly> {
+		let gppud = unsafe { GPIO_BASE.offset(37) as *mut u32 };
+		unsafe { RegField::new(WriteOnly(gppud), 2, 0) }
+	}
+	const fn gppudclk(pin: u8) -> RegField<WriteOnly> {
+		let gppudclk = unsafe { GPIO_BASE.offset(38 + pin as isize / 32) as *mut u32 };
+		let offset = pin as u32 % 32;
+		unsafe { RegField::new(WriteOnly(gppudclk), 1, offset) }
+	}
 	#[inline]
 	pub fn configure(&mut self, func: Func) {
+		let _guard = FSEL_LOCK.lock();
 		Self::gpfsel(self.pin).write(func.val());
 	}
 	#[inline]
@@ -74,8 +193,89 @@ impl Gpio {
 	pub fn low(&mut self) {
 		Self::gpclr(self.pin).write(1);
 	}
+	// The level on the pin, whatever its function is
+	#[inline]
+	pub fn read(&self) -> bool {
+		Self::gplev(self.pin).read() != 0
+	}
+	#[inline]
+	pub fn is_high(&self) -> bool {
+		self.read()
+	}
+	#[inline]
+	pub fn is_low(&self) -> bool {
+		!self.read()
+	}
+	// Set the control signal, give it 150 cycles to settle, clock it into the pin, and then take both away again.  The pin keeps its pull after that.
+	pub fn set_pull(&mut self, pull: Pull) {
+		let _guard = PULL_LOCK.lock();
+		Self::gppud().write(pull.val());
+		delay(150);
+		Self::gppudclk(self.pin).write(1);
+		delay(150);
+		Self::gppud().write(0);
+		Self::gppudclk(self.pin).write(0);
+	}
+}
+impl Drop for Gpio {
+	fn drop(&mut self) {
+		self.release_events();
+		release(1 << self.pin);
+	}
+}
+
+fn gpio_command(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	let (pin, action) = match args {
+		[pin, action] => (parse_number(pin)?, *action),
+		_ => return Err(CommandError::Usage),
+	};
+	if pin >= PIN_COUNT as u64 {
+		return Err(CommandError::Failed("there are only 54 pins"));
+	}
+	let pin = pin as u8;
+	// Reading doesn't change anything, so it works on pins that other drivers have
+	if action == "read" {
+		let level = Gpio::gplev(pin).read() != 0;
+		writeln!(out, "GPIO {}: {}", pin, if level { "high" } else { "low" })?;
+		return Ok(());
+	}
+	let mut gpio = Gpio::take(pin).ok_or(CommandError::Failed("that pin is in use (see pins)"))?;
+	match action {
+		"in" => gpio.configure(Func::Input),
+		"out" => gpio.configure(Func::Output),
+		"hi" => gpio.high(),
+		"lo" => gpio.low(),
+		"up" => gpio.set_pull(Pull::Up),
+		"down" => gpio.set_pull(Pull::Down),
+		"float" => gpio.set_pull(Pull::None),
+		_ => return Err(CommandError::Usage),
+	}
+	Ok(())
+}
+
+fn pins_command(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	if !args.is_empty() {
+		return Err(CommandError::Usage);
+	}
+	write_holders(out)?;
+	Ok(())
 }
 
+pub static COMMANDS: [Command; 2] = [
+	Command {
+		name: "gpio",
+		args: "<pin> in|out|hi|lo|up|down|float|read",
+		help: "Configure, drive, pull, or read a pin",
+		run: gpio_command,
+	},
+	Command {
+		name: "pins",
+		args: "",
+		help: "List the pins that are taken, and where they were taken",
+		run: pins_command,
+	},
+];
+
 #[cfg(all(not(target_arch = "aarch64"), test))]
 mod tests {
 	use super::*;
@@ -102,5 +302,60 @@ mod tests {
 		assert_eq!(Gpio::gpclr(29), unsafe {
 			RegField::new(WriteOnly(0x3F20_0028 as *mut u32), 1, 29)
 		});
+
+		// (should be GPLEV0, and GPLEV1 for the second bank)
+		assert_eq!(Gpio::gplev(29), unsafe {
+			RegField::new(ReadOnly(0x3F20_0034 as *const u32), 1, 29)
+		});
+		assert_eq!(Gpio::gplev(40), unsafe {
+			RegField::new(ReadOnly(0x3F20_0038 as *const u32), 1, 8)
+		});
+
+		// (should be GPPUD)
+		assert_eq!(Gpio::gppud(), unsafe {
+			RegField::new(WriteOnly(0x3F20_0094 as *mut u32), 2, 0)
+		});
+
+		// (should be GPPUDCLK0 and GPPUDCLK1)
+		assert_eq!(Gpio::gppudclk(29), unsafe {
+			RegField::new(WriteOnly(0x3F20_0098 as *mut u32), 1, 29)
+		});
+		assert_eq!(Gpio::gppudclk(53), unsafe {
+			RegField::new(WriteOnly(0x3F20_009C as *mut u32), 1, 21)
+		});
+	}
+
+	#[test]
+	fn check_pull_values() {
+		// GPPUD: 00 is off, 01 is pull down, 10 is pull up
+		assert_eq!(Pull::None.val(), 0);
+		assert_eq!(Pull::Down.val(), 1);
+		assert_eq!(Pull::Up.val(), 2);
+	}
+
+	#[test]
+	fn check_take() {
+		let first = Gpio::take(40).unwrap();
+		let line = line!() - 1;
+		assert!(Gpio::take(40).is_none());
+		// Other tests run at the same time and hold their own pins, so only look at ours
+		let pin_40 = || {
+			let mut report = String::new();
+			write_holders(&mut report).unwrap();
+			report
+				.lines()
+				.find(|l| l.starts_with("GPIO 40:"))
+				.map(String::from)
+		};
+		assert!(pin_40()
+			.unwrap()
+			.starts_with(&format!("GPIO 40: {}:{}:", file!(), line)));
+
+		// Dropping it gives it back
+		drop(first);
+		let second = Gpio::take(40);
+		assert!(second.is_some());
+		drop(second);
+		assert_eq!(pin_40(), None);
 	}
 }
@@ -0,0 +1,246 @@
+// Pins that are driven, read, or configured together.  Each register is touched once for the whole bank: pins that go high all change on one GPSET write (and pins that go low on one GPCLR write) instead of one write per pin, so a parallel bus doesn't go through a string of in-between values.
+use super::{claim, release, Func, FSEL_LOCK, GPIO_BASE, PIN_COUNT};
+use core::{
+	iter::FromIterator,
+	ops::{BitAnd, BitOr, Not},
+	panic::Location,
+	ptr,
+};
+
+// A set of pins, as a mask: bit n is pin n
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PinSet(u64);
+impl PinSet {
+	pub const EMPTY: PinSet = PinSet(0);
+	pub const ALL: PinSet = PinSet((1 << PIN_COUNT) - 1);
+
+	pub fn from_mask(mask: u64) -> Self {
+		assert_eq!(mask & !Self::ALL.0, 0, "there are only 54 pins");
+		Self(mask)
+	}
+	pub fn from_pins(pins: &[u8]) -> Self {
+		pins.iter().copied().collect()
+	}
+	// Bit i of value goes to pins[i].  For putting a word out on a bus whose pins aren't in order.
+	pub fn spread(pins: &[u8], value: u64) -> Self {
+		pins.iter()
+			.enumerate()
+			.filter(|(i, _)| value & (1 << i) != 0)
+			.map(|(_, pin)| *pin)
+			.collect()
+	}
+	// The other way around: bit i of the result is whether pins[i] is in the set
+	pub fn gather(self, pins: &[u8]) -> u64 {
+		pins.iter()
+			.enumerate()
+			.filter(|(_, pin)| self.contains(**pin))
+			.fold(0, |value, (i, _)| value | 1 << i)
+	}
+	pub const fn mask(self) -> u64 {
+		self.0
+	}
+	pub fn with(self, pin: u8) -> Self {
+		assert!(pin < PIN_COUNT);
+		Self(self.0 | 1 << pin)
+	}
+	pub fn without(self, pin: u8) -> Self {
+		Self(self.0 & !(1 << pin))
+	}
+	pub fn contains(self, pin: u8) -> bool {
+		pin < PIN_COUNT && self.0 & (1 << pin) != 0
+	}
+	pub fn is_subset(self, other: PinSet) -> bool {
+		self.0 & !other.0 == 0
+	}
+	pub fn is_empty(self) -> bool {
+		self.0 == 0
+	}
+	pub fn len(self) -> usize {
+		self.0.count_ones() as usize
+	}
+	pub fn iter(self) -> impl Iterator<Item = u8> {
+		(0..PIN_COUNT).filter(move |pin| self.contains(*pin))
+	}
+	// The halves that go in the pins 0-31 and pins 32-53 registers
+	fn words(self) -> [u32; 2] {
+		[self.0 as u32, (self.0 >> 32) as u32]
+	}
+	fn from_words(words: [u32; 2]) -> Self {
+		Self((words[1] as u64) << 32 | words[0] as u64)
+	}
+}
+impl BitOr for PinSet {
+	type Output = PinSet;
+	fn bitor(self, rhs: PinSet) -> PinSet {
+		PinSet(self.0 | rhs.0)
+	}
+}
+impl BitAnd for PinSet {
+	type Output = PinSet;
+	fn bitand(self, rhs: PinSet) -> PinSet {
+		PinSet(self.0 & rhs.0)
+	}
+}
+impl Not for PinSet {
+	type Output = PinSet;
+	fn not(self) -> PinSet {
+		PinSet(!self.0 & Self::ALL.0)
+	}
+}
+impl FromIterator<u8> for PinSet {
+	fn from_iter<I: IntoIterator<Item = u8>>(pins: I) -> Self {
+		pins.into_iter().fold(Self::EMPTY, |set, pin| set.with(pin))
+	}
+}
+
+// What to change in each GPFSEL register that has pins in the set: (register, mask, value)
+fn fsel_updates(pins: PinSet, func: Func) -> impl Iterator<Item = (usize, u32, u32)> {
+	(0..6).filter_map(move |reg| {
+		let (mask, value) = pins
+			.iter()
+			.filter(|pin| *pin as usize / 10 == reg)
+			.map(|pin| (pin as u32 % 10) * 3)
+			.fold((0, 0), |(mask, value), offset| {
+				(mask | 0b111 << offset, value | func.val() << offset)
+			});
+		if mask == 0 {
+			None
+		} else {
+			Some((reg, mask, value))
+		}
+	})
+}
+
+// GPFSEL0 is word 0, GPSET0 is 7, GPCLR0 is 10, and GPLEV0 is 13
+fn register(word: usize) -> *mut u32 {
+	unsafe { GPIO_BASE.add(word) as *mut u32 }
+}
+
+// Owns every pin in its set, the same way a Gpio owns its pin
+pub struct GpioBank {
+	pins: PinSet,
+}
+impl GpioBank {
+	// All of the pins or none of them
+	#[track_caller]
+	pub fn take(pins: PinSet) -> Option<Self> {
+		if claim(pins.mask(), Location::caller()) {
+			Some(Self { pins })
+		} else {
+			None
+		}
+	}
+	pub fn pins(&self) -> PinSet {
+		self.pins
+	}
+	// One read-modify-write per GPFSEL register, instead of one per pin
+	pub fn configure(&mut self, func: Func) {
+		let _guard = FSEL_LOCK.lock();
+		for (reg, mask, value) in fsel_updates(self.pins, func) {
+			let fsel = register(reg);
+			unsafe { ptr::write_volatile(fsel, (ptr::read_volatile(fsel) & !mask) | value) };
+		}
+	}
+	// Drive the pins in high high and the rest of the bank low.  GPSET and GPCLR are separate registers, so the pins going low change first, and then the ones going high.
+	pub fn write(&mut self, high: PinSet) {
+		assert!(high.is_subset(self.pins));
+		self.set_low(self.pins & !high);
+		self.set_high(high);
+	}
+	pub fn set_high(&mut self, pins: PinSet) {
+		self.write_words(7, pins);
+	}
+	pub fn set_low(&mut self, pins: PinSet) {
+		self.write_words(10, pins);
+	}
+	// Writing 0 to GPSET / GPCLR doesn't do anything, so banks without any of the pins are skipped
+	fn write_words(&mut self, first: usize, pins: PinSet) {
+		assert!(pins.is_subset(self.pins));
+		for (i, word) in pins.words().iter().enumerate() {
+			if *word != 0 {
+				unsafe { ptr::write_volatile(register(first + i), *word) };
+			}
+		}
+	}
+	// The levels of every pin in the bank, from one read of each GPLEV register that has pins in it
+	pub fn read(&self) -> PinSet {
+		let mut words = [0; 2];
+		for (i, word) in self.pins.words().iter().enumerate() {
+			if *word != 0 {
+				words[i] = unsafe { ptr::read_volatile(register(13 + i)) };
+			}
+		}
+		PinSet::from_words(words) & self.pins
+	}
+}
+impl Drop for GpioBank {
+	fn drop(&mut self) {
+		release(self.pins.mask());
+	}
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::super::Gpio;
+	use super::*;
+
+	#[test]
+	fn check_pin_set() {
+		let set = PinSet::from_pins(&[2, 31, 32, 53]);
+		assert_eq!(set.mask(), 1 << 2 | 1 << 31 | 1 << 32 | 1 << 53);
+		assert_eq!(set.words(), [1 << 2 | 1 << 31, 1 | 1 << 21]);
+		assert_eq!(PinSet::from_words(set.words()), set);
+		assert_eq!(set.len(), 4);
+		assert!(set.contains(32) && !set.contains(33) && !set.contains(64));
+		assert_eq!(set.iter().collect::<Vec<_>>(), [2, 31, 32, 53]);
+
+		assert_eq!(set.without(31).with(4), PinSet::from_pins(&[2, 4, 32, 53]));
+		assert_eq!(!PinSet::EMPTY, PinSet::ALL);
+		assert_eq!((!set & set), PinSet::EMPTY);
+		assert_eq!(set & PinSet::from_pins(&[2, 3]), PinSet::from_pins(&[2]));
+		assert!(PinSet::from_pins(&[31, 53]).is_subset(set));
+		assert!(!(set | PinSet::from_pins(&[3])).is_subset(set));
+	}
+
+	#[test]
+	fn check_spread_gather() {
+		// An 8 bit bus wired to whatever pins were free
+		let bus = [5, 6, 13, 19, 26, 12, 16, 20];
+		let set = PinSet::spread(&bus, 0b1010_0110);
+		assert_eq!(set, PinSet::from_pins(&[6, 13, 12, 20]));
+		assert_eq!(set.gather(&bus), 0b1010_0110);
+		assert_eq!(PinSet::ALL.gather(&bus), 0xFF);
+	}
+
+	#[test]
+	fn check_fsel_updates() {
+		// Pins 8 and 9 are in GPFSEL0, 10 and 19 in GPFSEL1, and 53 in GPFSEL5
+		let set = PinSet::from_pins(&[8, 9, 10, 19, 53]);
+		let updates: Vec<_> = fsel_updates(set, Func::Output).collect();
+		assert_eq!(
+			updates,
+			[
+				(0, 0b111 << 27 | 0b111 << 24, 0b001 << 27 | 0b001 << 24),
+				(1, 0b111 << 27 | 0b111, 0b001 << 27 | 0b001),
+				(5, 0b111 << 9, 0b001 << 9),
+			]
+		);
+		// Alt4 is 011
+		let updates: Vec<_> = fsel_updates(PinSet::from_pins(&[21]), Func::Alt4).collect();
+		assert_eq!(updates, [(2, 0b111 << 3, 0b011 << 3)]);
+	}
+
+	#[test]
+	fn check_bank_take() {
+		let bank = GpioBank::take(PinSet::from_pins(&[41, 42])).unwrap();
+		assert!(Gpio::take(42).is_none());
+		// Overlapping banks don't get any of their pins
+		assert!(GpioBank::take(PinSet::from_pins(&[42, 43])).is_none());
+		let gpio = Gpio::take(43);
+		assert!(gpio.is_some());
+		drop(gpio);
+
+		drop(bank);
+		assert!(GpioBank::take(PinSet::from_pins(&[41, 42, 43])).is_some());
+	}
+}
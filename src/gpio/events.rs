@@ -0,0 +1,250 @@
+// Edge and level detection.  A pin that's listening sets its bit in GPEDS when the event happens, which raises the GPIO interrupt for its bank, and the interrupt handler calls the pin's handler.
+use super::{Gpio, PIN_COUNT};
+use crate::memory::gpio::*;
+use crate::register::{ReadOnly, ReadWrite, RegField, WriteOnly};
+use crate::sync::SpinLock;
+use core::{
+	ptr,
+	sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+	// Edges, sampled with the system clock, so glitches that are shorter than a couple of samples get filtered out (GPREN / GPFEN)
+	Rising,
+	Falling,
+	// While the pin is high / low (GPHEN / GPLEN).  The event comes right back after it's cleared, so the handler has to do something about the level, or stop listening.
+	High,
+	Low,
+	// Edges that aren't sampled, for signals that are too fast for Rising / Falling (GPAREN / GPAFEN)
+	AsyncRising,
+	AsyncFalling,
+}
+impl Trigger {
+	const ALL: [Trigger; 6] = [
+		Trigger::Rising,
+		Trigger::Falling,
+		Trigger::High,
+		Trigger::Low,
+		Trigger::AsyncRising,
+		Trigger::AsyncFalling,
+	];
+	fn enable_register(self) -> *mut u32 {
+		match self {
+			Trigger::Rising => GPREN0,
+			Trigger::Falling => GPFEN0,
+			Trigger::High => GPHEN0,
+			Trigger::Low => GPLEN0,
+			Trigger::AsyncRising => GPAREN0,
+			Trigger::AsyncFalling => GPAFEN0,
+		}
+	}
+}
+
+// Called from the IRQ handler with the pin that had the event
+pub type EventHandler = fn(pin: u8);
+
+// fn pointers as usize, like the IRQ handlers.  0 means no handler.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static HANDLERS: [AtomicUsize; PIN_COUNT as usize] = [NO_HANDLER; PIN_COUNT as usize];
+// The debounce window (0 for none) and when the last event that got through happened, both in system timer microseconds
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static DEBOUNCE: [AtomicU64; PIN_COUNT as usize] = [ZERO; PIN_COUNT as usize];
+static LAST_EVENT: [AtomicU64; PIN_COUNT as usize] = [ZERO; PIN_COUNT as usize];
+
+// The enable registers are shared by 32 pins, so changing one is a read-modify-write
+static ENABLE_LOCK: SpinLock<()> = SpinLock::new(());
+
+impl Gpio {
+	fn event_enable(trigger: Trigger, pin: u8) -> RegField<ReadWrite> {
+		let reg = unsafe { trigger.enable_register().offset(pin as isize / 32) };
+		let offset = pin as u32 % 32;
+		unsafe { RegField::new(ReadWrite(reg), 1, offset) }
+	}
+	// GPEDS bits are cleared by writing 1, so clearing one doesn't touch the others
+	fn gpeds(pin: u8) -> RegField<WriteOnly> {
+		let gpeds = unsafe { GPEDS0.offset(pin as isize / 32) };
+		let offset = pin as u32 % 32;
+		unsafe { RegField::new(WriteOnly(gpeds), 1, offset) }
+	}
+	fn gpeds_status(pin: u8) -> RegField<ReadOnly> {
+		let gpeds = unsafe { GPEDS0.offset(pin as isize / 32) as *const u32 };
+		let offset = pin as u32 % 32;
+		unsafe { RegField::new(ReadOnly(gpeds), 1, offset) }
+	}
+	// A pin can listen for more than one trigger at a time
+	pub fn listen(&mut self, trigger: Trigger) {
+		let _guard = ENABLE_LOCK.lock();
+		Self::event_enable(trigger, self.pin).write(1);
+	}
+	pub fn unlisten(&mut self, trigger: Trigger) {
+		let _guard = ENABLE_LOCK.lock();
+		Self::event_enable(trigger, self.pin).write(0);
+	}
+	pub fn unlisten_all(&mut self) {
+		let _guard = ENABLE_LOCK.lock();
+		for trigger in Trigger::ALL.iter() {
+			Self::event_enable(*trigger, self.pin).write(0);
+		}
+	}
+	// For polling.  Once any pin has a handler, the GPIO interrupts are on and the IRQ handler clears every event, so this only works before then.
+	pub fn event_detected(&self) -> bool {
+		Self::gpeds_status(self.pin).read() != 0
+	}
+	pub fn clear_event(&mut self) {
+		Self::gpeds(self.pin).write(1);
+	}
+	// Called when the Gpio is dropped, so a pin that's given back doesn't keep calling its old handler.  A polled pin can have triggers enabled without a handler too, so they're cleared either way.
+	pub(super) fn release_events(&mut self) {
+		HANDLERS[self.pin as usize].store(0, Ordering::Release);
+		// The host tests drop pins too, and there's no GPIO block to write to there
+		if cfg!(target_arch = "aarch64") {
+			self.unlisten_all();
+			self.clear_event();
+		}
+	}
+}
+
+// Whether an event at now (in microseconds) is far enough from the last one to count.  Updates the last event if it is.
+fn debounce(pin: usize, now: u64) -> bool {
+	let window = DEBOUNCE[pin].load(Ordering::Relaxed);
+	let last = LAST_EVENT[pin].load(Ordering::Relaxed);
+	if window != 0 && last != 0 && now.saturating_sub(last) < window {
+		return false;
+	}
+	LAST_EVENT[pin].store(now, Ordering::Relaxed);
+	true
+}
+
+#[cfg(target_arch = "aarch64")]
+mod irq {
+	use super::*;
+	use crate::{
+		gpio::{Input, Pin},
+		interrupts::{self, IrqSource},
+		timer::{self, Duration},
+	};
+	use core::sync::atomic::AtomicBool;
+
+	// GPU IRQs 49-51 are gpio_int[0-2], one for each bank (pins 0-27, 28-45, and 46-53).  52 is all of them at once, so it's left off, otherwise every event would be taken twice.
+	const GPIO_IRQS: [u8; 3] = [49, 50, 51];
+	static IRQS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+	impl Pin<Input> {
+		// Call handler (from the IRQ handler) whenever trigger happens on this pin.  Events that come less than debounce after the last one are dropped.  This replaces the pin's old handler, but keeps listening for its old triggers.
+		pub fn on_event(
+			&mut self,
+			trigger: Trigger,
+			handler: EventHandler,
+			debounce: Option<Duration>,
+		) {
+			let pin = self.pin() as usize;
+			DEBOUNCE[pin].store(debounce.map_or(0, |d| d.as_micros()), Ordering::Relaxed);
+			LAST_EVENT[pin].store(0, Ordering::Relaxed);
+			HANDLERS[pin].store(handler as usize, Ordering::Release);
+			if !IRQS_ENABLED.swap(true, Ordering::AcqRel) {
+				for n in GPIO_IRQS.iter() {
+					interrupts::register_handler(IrqSource::Gpu(*n), handle_events);
+				}
+			}
+			// Don't report something that happened before we were listening
+			self.gpio.clear_event();
+			self.gpio.listen(trigger);
+		}
+		pub fn remove_event_handler(&mut self) {
+			self.gpio.release_events();
+		}
+	}
+
+	// All three IRQs share this.  Whichever one comes first handles every bank.
+	fn handle_events() {
+		let now = timer::now().as_micros();
+		for bank in 0..2 {
+			let gpeds = unsafe { GPEDS0.offset(bank) };
+			let mut pending = unsafe { ptr::read_volatile(gpeds) };
+			// Only clear what we're about to handle, so that an event that comes in now isn't lost
+			unsafe { ptr::write_volatile(gpeds, pending) };
+			while pending != 0 {
+				let bit = pending.trailing_zeros();
+				pending &= !(1 << bit);
+				dispatch(bank as u8 * 32 + bit as u8, now);
+			}
+		}
+	}
+
+	fn dispatch(pin: u8, now: u64) {
+		let handler = HANDLERS[pin as usize].load(Ordering::Acquire);
+		if handler == 0 {
+			// Same as an IRQ without a handler: turn it off instead of taking it forever
+			{
+				let _guard = ENABLE_LOCK.lock();
+				for trigger in Trigger::ALL.iter() {
+					Gpio::event_enable(*trigger, pin).write(0);
+				}
+			}
+			warn!("Stopped listening for events on GPIO {}: no handler", pin);
+		} else if debounce(pin as usize, now) {
+			let handler: EventHandler = unsafe { core::mem::transmute(handler) };
+			handler(pin);
+		}
+	}
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_event_registers() {
+		// (should be GPREN0)
+		assert_eq!(Gpio::event_enable(Trigger::Rising, 17), unsafe {
+			RegField::new(ReadWrite(0x3F20_004C as *mut u32), 1, 17)
+		});
+		// (should be GPAFEN1)
+		assert_eq!(Gpio::event_enable(Trigger::AsyncFalling, 40), unsafe {
+			RegField::new(ReadWrite(0x3F20_008C as *mut u32), 1, 8)
+		});
+		// (should be GPHEN0 and GPLEN1)
+		assert_eq!(Gpio::event_enable(Trigger::High, 0), unsafe {
+			RegField::new(ReadWrite(0x3F20_0064 as *mut u32), 1, 0)
+		});
+		assert_eq!(Gpio::event_enable(Trigger::Low, 53), unsafe {
+			RegField::new(ReadWrite(0x3F20_0074 as *mut u32), 1, 21)
+		});
+		// (should be GPEDS1)
+		assert_eq!(Gpio::gpeds(33), unsafe {
+			RegField::new(WriteOnly(0x3F20_0044 as *mut u32), 1, 1)
+		});
+
+		// Pin 31 is the top bit of GPREN0
+		assert_eq!(Gpio::event_enable(Trigger::Rising, 31), unsafe {
+			RegField::new(ReadWrite(0x3F20_004C as *mut u32), 1, 31)
+		});
+		let mut gpren0 = 0x4000_0001u32;
+		let mut field = unsafe { RegField::new(ReadWrite(ptr::addr_of_mut!(gpren0)), 1, 31) };
+		field.write(1);
+		assert_eq!(gpren0, 0xC000_0001);
+		assert_eq!(field.read(), 1);
+		field.write(0);
+		assert_eq!(gpren0, 0x4000_0001);
+	}
+
+	#[test]
+	fn check_debounce() {
+		let pin = 5;
+		DEBOUNCE[pin].store(10_000, Ordering::Relaxed);
+		assert!(debounce(pin, 1_000_000));
+		// Bounces
+		assert!(!debounce(pin, 1_000_500));
+		assert!(!debounce(pin, 1_009_999));
+		// The window starts at the last event that got through, not the last bounce
+		assert!(debounce(pin, 1_010_000));
+		assert!(!debounce(pin, 1_015_000));
+
+		// Without a window, everything gets through
+		DEBOUNCE[pin].store(0, Ordering::Relaxed);
+		assert!(debounce(pin, 1_015_001));
+	}
+}
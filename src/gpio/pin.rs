@@ -0,0 +1,99 @@
+// Typestate pins: a Pin's function is part of its type, so driving an input (or the UART's TX pin) doesn't compile.  Gpio is still there as an escape hatch for anything this doesn't cover.
+use super::{Func, Gpio, Pull};
+use core::marker::PhantomData;
+
+pub struct Input;
+pub struct Output;
+// Alternate function N.  Which peripheral that is depends on the pin (see the BCM2835 peripherals datasheet, section 6.2).
+pub struct Alt<const N: u8>;
+pub type Alt0 = Alt<0>;
+pub type Alt1 = Alt<1>;
+pub type Alt2 = Alt<2>;
+pub type Alt3 = Alt<3>;
+pub type Alt4 = Alt<4>;
+pub type Alt5 = Alt<5>;
+
+pub trait AltFunction {
+	const FUNC: Func;
+}
+macro_rules! alt_functions {
+	($($n:literal => $func:ident),*) => {
+		$(impl AltFunction for Alt<$n> {
+			const FUNC: Func = Func::$func;
+		})*
+	};
+}
+alt_functions!(0 => Alt0, 1 => Alt1, 2 => Alt2, 3 => Alt3, 4 => Alt4, 5 => Alt5);
+
+pub struct Pin<Mode> {
+	pub(super) gpio: Gpio,
+	mode: PhantomData<Mode>,
+}
+impl<Mode> Pin<Mode> {
+	fn new(gpio: Gpio) -> Self {
+		Self {
+			gpio,
+			mode: PhantomData,
+		}
+	}
+	pub fn pin(&self) -> u8 {
+		self.gpio.pin()
+	}
+	pub fn into_input(self, pull: Pull) -> Pin<Input> {
+		self.gpio.into_input(pull)
+	}
+	pub fn into_output(self) -> Pin<Output> {
+		self.gpio.into_output()
+	}
+	pub fn into_alt<A: AltFunction>(self) -> Pin<A> {
+		self.gpio.into_alt()
+	}
+	// Back to the raw API.  The pin stays taken.
+	pub fn into_gpio(self) -> Gpio {
+		self.gpio
+	}
+}
+impl Pin<Output> {
+	#[inline]
+	pub fn set_high(&mut self) {
+		self.gpio.high();
+	}
+	#[inline]
+	pub fn set_low(&mut self) {
+		self.gpio.low();
+	}
+	#[inline]
+	pub fn set(&mut self, high: bool) {
+		if high {
+			self.set_high();
+		} else {
+			self.set_low();
+		}
+	}
+}
+impl Pin<Input> {
+	#[inline]
+	pub fn is_high(&self) -> bool {
+		self.gpio.read()
+	}
+	#[inline]
+	pub fn is_low(&self) -> bool {
+		!self.is_high()
+	}
+}
+
+impl Gpio {
+	pub fn into_input(mut self, pull: Pull) -> Pin<Input> {
+		self.configure(Func::Input);
+		self.set_pull(pull);
+		Pin::new(self)
+	}
+	pub fn into_output(mut self) -> Pin<Output> {
+		self.configure(Func::Output);
+		Pin::new(self)
+	}
+	pub fn into_alt<A: AltFunction>(mut self) -> Pin<A> {
+		self.configure(A::FUNC);
+		Pin::new(self)
+	}
+}
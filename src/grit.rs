@@ -1,5 +1,10 @@
-use super::{main, uart::Uart1};
-use core::{fmt::Write, panic::PanicInfo};
+#[cfg(feature = "chainloader")]
+use super::chainloader;
+use super::{
+	console, cpu, main,
+	memory::{self, paging},
+};
+use core::panic::PanicInfo;
 
 // pub fn get_el() -> u8 {
 // 	let current_el: u64;
@@ -18,13 +23,14 @@ extern "C" {
 #[cfg(target_arch = "aarch64")]
 #[panic_handler]
 fn handle_panic(panic_info: &PanicInfo) -> ! {
-	let mut uart1 = Uart1::new();
-	write!(&mut uart1, "\r\npanic occurred: {:#?}", panic_info).unwrap();
+	// Whoever had the console (maybe us, maybe a core that's never going to let go) isn't getting it back.  eprintln flushes, because interrupts might be masked and there'd be nobody to send what's left in the buffer.
+	unsafe { console::force_unlock() };
+	eprintln!("\npanic occurred: {:#?}", panic_info);
 	halt();
 }
 
 #[cfg(target_arch = "aarch64")]
-fn halt() -> ! {
+pub(crate) fn halt() -> ! {
 	loop {
 		unsafe {
 			asm!("wfe");
@@ -53,7 +59,7 @@ fn halt() -> ! {
 #[link_section = ".boot"]
 #[naked]
 pub unsafe extern "C" fn _start() -> ! {
-	// TODO: Setup the non-boot cores
+	// The armstub keeps the non-boot cores in its spin table (see cpu::start_core), so they should never get here.  Park them if they do.
 	asm!(
 		"mrs x8, mpidr_el1",
 		"tst x8, #0x3",
@@ -62,11 +68,13 @@ pub unsafe extern "C" fn _start() -> ! {
 		"wfe",
 		"b 2b",
 		"3:",
+		"bl {}",
 		// "ldr x8, {}",
 		// "mov sp, x8",
 		"adrp x1, {}",
 		"mov sp, x1",
 		"b {}",
+		sym _drop_to_kernel_el,
 		// const 0x80_000,
 		sym __stack_start,
 		// sym _start,
@@ -75,10 +83,124 @@ pub unsafe extern "C" fn _start() -> ! {
 	);
 }
 
+const SCR_NS: u64 = 1 << 0;
+const SCR_RES1: u64 = 0b11 << 4;
+const SCR_SMD: u64 = 1 << 7;
+const SCR_HCE: u64 = 1 << 8;
+const SCR_RW: u64 = 1 << 10;
+// Non-secure, EL2 is aarch64, no SMC.  IRQ / FIQ / SError are not routed to EL3.
+const SCR_EL3: u64 = SCR_NS | SCR_RES1 | SCR_SMD | SCR_HCE | SCR_RW;
+
+const HCR_FMO: u64 = 1 << 3;
+const HCR_IMO: u64 = 1 << 4;
+const HCR_AMO: u64 = 1 << 5;
+const HCR_RW: u64 = 1 << 31;
+// EL1 is aarch64.  If the kernel runs at EL2 then physical interrupts have to be routed to EL2 or it will never see them.
+const HCR_EL2: u64 = HCR_RW
+	| if let cpu::ExceptionLevel::EL2 = cpu::KERNEL_EL {
+		HCR_FMO | HCR_IMO | HCR_AMO
+	} else {
+		0
+	};
+
+// Little endian, MMU / caches / alignment checks off.  Everything else is RES1.
+const SCTLR_EL2: u64 = 0x30c5_0830;
+const SCTLR_EL1: u64 = 0x30d0_0800;
+
+// Don't trap FP / SIMD at EL2 (CPTR_EL2.TFP), or at EL1 and EL0 (CPACR_EL1.FPEN)
+const CPTR_EL2: u64 = 0x33ff;
+const CPACR_EL1: u64 = 0b11 << 20;
+
+// Let EL1 use the physical counter and timer
+const CNTHCTL_EL2: u64 = 0b11;
+
+// Enter KERNEL_EL using SP_ELx, with all of DAIF masked.
+const SPSR_KERNEL: u64 = (0b1111 << 6) | ((cpu::KERNEL_EL as u64) << 2) | 1;
+
+// STAGE 0.5: Every core comes through here before it has a stack.  We're called with bl and "return" to x30 at KERNEL_EL, which means an eret from EL3 (our armstub) or EL2 (the stock armstub).  If we're already at KERNEL_EL (e.g. we've been chainloaded), this is a plain ret.  Only clobbers x8 and x9.
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn _drop_to_kernel_el() {
+	asm!(
+		"mrs x8, CurrentEL",
+		"ubfx x8, x8, #2, #2",
+		"cmp x8, #{kernel_el}",
+		"b.ls 9f",
+		// Setup EL2.  This matters even for an EL1 kernel: HCR_EL2.RW is what makes EL1 aarch64.
+		"movz x9, #{hcr_lo}",
+		"movk x9, #{hcr_hi}, lsl #16",
+		"msr HCR_EL2, x9",
+		"movz x9, #{sctlr2_lo}",
+		"movk x9, #{sctlr2_hi}, lsl #16",
+		"msr SCTLR_EL2, x9",
+		"mov x9, #{cptr}",
+		"msr CPTR_EL2, x9",
+		"mov x9, #{cnthctl}",
+		"msr CNTHCTL_EL2, x9",
+		"msr CNTVOFF_EL2, xzr",
+		// Setup EL1
+		"movz x9, #{sctlr1_lo}",
+		"movk x9, #{sctlr1_hi}, lsl #16",
+		"msr SCTLR_EL1, x9",
+		"mov x9, #{cpacr}",
+		"msr CPACR_EL1, x9",
+		"mov x9, #{spsr}",
+		"cmp x8, #3",
+		"b.ne 2f",
+		// Leave EL3
+		"mov x8, #{scr}",
+		"msr SCR_EL3, x8",
+		"msr SPSR_EL3, x9",
+		"msr ELR_EL3, x30",
+		"eret",
+		// Leave EL2 (only happens for an EL1 kernel)
+		"2:",
+		"msr SPSR_EL2, x9",
+		"msr ELR_EL2, x30",
+		"eret",
+		"9:",
+		"ret",
+		kernel_el = const cpu::KERNEL_EL as u64,
+		hcr_lo = const HCR_EL2 & 0xffff,
+		hcr_hi = const HCR_EL2 >> 16,
+		sctlr2_lo = const SCTLR_EL2 & 0xffff,
+		sctlr2_hi = const SCTLR_EL2 >> 16,
+		sctlr1_lo = const SCTLR_EL1 & 0xffff,
+		sctlr1_hi = const SCTLR_EL1 >> 16,
+		cptr = const CPTR_EL2,
+		cnthctl = const CNTHCTL_EL2,
+		cpacr = const CPACR_EL1,
+		spsr = const SPSR_KERNEL,
+		scr = const SCR_EL3,
+		options(noreturn)
+	);
+}
+
 unsafe fn get_bss() -> &'static mut [u8] {
 	let start = core::ptr::addr_of_mut!(__bss_start);
 	let end = core::ptr::addr_of!(__bss_end);
-	core::slice::from_raw_parts_mut(start, start.offset_from(end) as usize)
+	core::slice::from_raw_parts_mut(start, end.offset_from(start) as usize)
+}
+
+// STAGE 0 (secondary cores): The armstub branches here once cpu::start_core has written our address into the spin table.  The boot core has already put our stack pointer into CORE_STACK_TOP.
+#[no_mangle]
+#[naked]
+pub unsafe extern "C" fn _start_secondary() -> ! {
+	asm!(
+		"bl {}",
+		"mrs x0, mpidr_el1",
+		"and x0, x0, #0x3",
+		"adrp x1, {}",
+		"add x1, x1, :lo12:{}",
+		"ldr x1, [x1, x0, lsl #3]",
+		"mov sp, x1",
+		"b {}",
+		sym _drop_to_kernel_el,
+		sym cpu::CORE_STACK_TOP,
+		sym cpu::CORE_STACK_TOP,
+		sym rust_secondary_entry,
+		options(noreturn)
+	);
 }
 
 // STAGE 1: Now that the stack pointer is setup and only one processor is running, we need to clear BSS and (TODO) setup globals.
@@ -88,6 +210,19 @@ fn rust_entry() -> ! {
 	let bss = unsafe { get_bss() };
 	bss.fill(0);
 
-	// Break to main
+	// Atomics need cacheable memory, so turn on the MMU before anything else.
+	paging::init();
+	memory::init();
+
+	// Break to main, or go get the kernel that we're supposed to run instead
+	#[cfg(feature = "chainloader")]
+	chainloader::run();
+	#[cfg(not(feature = "chainloader"))]
 	main();
 }
+
+// STAGE 1 (secondary cores): BSS was cleared by the boot core, so all that's left is to run the entry we were given.
+#[no_mangle]
+extern "C" fn rust_secondary_entry(core: usize) -> ! {
+	cpu::run_secondary(core);
+}
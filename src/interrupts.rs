@@ -1,15 +1,21 @@
 use bitvec::{array::BitArray, order::Msb0};
 
 use crate::memory::interrupts::*;
-use crate::memory::timer::TIMER_CONTROL_STATUS;
+use alloc::boxed::Box;
 
 use super::{
 	cpu::ExceptionLevel,
 	delay,
+	generic_timer::Timer,
 	gpio::{self, Gpio},
-	uart::Uart1,
+	local_intc,
+	shell::{Command, CommandError},
+	syndrome::{ExceptionClass, Syndrome},
+};
+use core::{
+	fmt::Write,
+	sync::atomic::{AtomicUsize, Ordering},
 };
-use core::fmt::Write;
 
 extern "C" {
 	static __int_vec_base: u8;
@@ -20,163 +26,414 @@ extern "C" {
 	The base (bus) address for the system timer is: 0x7E003000
 */
 
+// Everything the vector stubs save about the interrupted context.  Handlers can modify this, and the changes are restored before the eret.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapFrame {
+	pub x: [u64; 31],
+	// The interrupted stack pointer.  Changing it only takes effect if the interrupted code was running on SP_EL0.
+	pub sp: u64,
+	pub elr: u64,
+	pub spsr: u64,
+	pub esr: u64,
+	pub far: u64,
+}
+impl TrapFrame {
+	// Resume after the instruction that caused a synchronous exception, instead of retrying it.
+	pub fn skip_instruction(&mut self) {
+		self.elr += Syndrome(self.esr).instruction_length() as u64;
+	}
+}
+const FRAME_SIZE: usize = core::mem::size_of::<TrapFrame>();
+
+// Which of the 16 entries in the vector table was taken.
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VectorKind {
+	CurrentSp0Sync = 0,
+	CurrentSp0Irq,
+	CurrentSp0Fiq,
+	CurrentSp0SError,
+	CurrentSpxSync,
+	CurrentSpxIrq,
+	CurrentSpxFiq,
+	CurrentSpxSError,
+	Lower64Sync,
+	Lower64Irq,
+	Lower64Fiq,
+	Lower64SError,
+	Lower32Sync,
+	Lower32Irq,
+	Lower32Fiq,
+	Lower32SError,
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExceptionType {
+	Sync,
+	Irq,
+	Fiq,
+	SError,
+}
+impl VectorKind {
+	pub fn exception_type(self) -> ExceptionType {
+		match self as u64 % 4 {
+			0 => ExceptionType::Sync,
+			1 => ExceptionType::Irq,
+			2 => ExceptionType::Fiq,
+			_ => ExceptionType::SError,
+		}
+	}
+}
+
 // We use the same interrupt vector for all exception levels, so this handler is called for all exceptions.
 #[no_mangle]
-pub extern "C" fn interrupt_handler() {
-	// TODO: Make this function work for more then just el3
-	let link: *const u8;
-	let esr: u64;
-	let far: u64;
-	let mut elr: *const u8;
-	unsafe {
-		asm!(
-			"mov {}, x30",
-			"mrs {:x}, ESR_EL3",
-			"mrs {:x}, FAR_EL3",
-			"mrs {:x}, ELR_EL3",
-			out(reg) link,
-			out(reg) esr,
-			out(reg) far,
-			out(reg) elr
-		);
+pub extern "C" fn interrupt_handler(frame: &mut TrapFrame, kind: VectorKind) {
+	if kind.exception_type() == ExceptionType::Irq {
+		dispatch_irqs();
+		return;
 	}
-	let vbase = unsafe { core::ptr::addr_of!(__int_vec_base) };
-	let id = unsafe { link.offset_from(vbase) } / 128;
-	let instruction_length = (esr >> 25) & 0b1;
-	let exception_class = (esr >> 26) & 0b111111;
-	let mut uart1 = Uart1::new();
-	writeln!(&mut uart1, "\nException occured ({}):", id).unwrap();
-	writeln!(&mut uart1, "- Link Register: {:p}", link).unwrap();
-	writeln!(
-		&mut uart1,
-		"- Syndrome: {:b} {:b}",
-		exception_class, instruction_length
-	)
-	.unwrap();
-	writeln!(&mut uart1, "- Fault Address: {}", far).unwrap();
-	writeln!(&mut uart1, "- Exception Link: {:p}", elr).unwrap();
-	match id {
-		0 | 4 | 8 | 12 => {
-			// Sync
-		}
-		1 | 5 | 9 | 13 => {
-			// IRQ
-			let basic = unsafe { core::ptr::read_volatile(IRQ_PEND_BASIC) };
-			writeln!(&mut uart1, "- IRQ Basic: {:b}", basic).unwrap();
-			if basic & 0b100000000 != 0 {
-				let irq1 = unsafe { core::ptr::read_volatile(IRQ_PEND_1) };
-				writeln!(&mut uart1, "  - IRQ 1: {:b}", irq1).unwrap();
-				unsafe {
-					core::ptr::write_volatile(IRQ_PEND_1, 0);
-				}
-				if irq1 & 0b10 != 0 {
-					writeln!(&mut uart1, "  - TIMER_CS: {:b}", unsafe {
-						core::ptr::read_volatile(TIMER_CONTROL_STATUS)
-					})
-					.unwrap();
-					unsafe { core::ptr::write_volatile(TIMER_CONTROL_STATUS, 0b10) };
-				}
+	let syndrome = Syndrome(frame.esr);
+	eprintln!("\nException occured ({:?}):", kind);
+	eprintln!("- Syndrome: {}", syndrome);
+	eprintln!("- Fault Address: {:#x}", frame.far);
+	eprintln!("- Exception Link: {:p}", frame.elr as *const u8);
+	match kind.exception_type() {
+		ExceptionType::Sync => {
+			// SVC, HVC, and SMC already return to the next instruction, but BRK would just trap again.
+			if syndrome.class() == ExceptionClass::Brk64 {
+				frame.skip_instruction();
 			}
-			if basic & 0b1000000000 != 0 {
-				let irq2 = unsafe { core::ptr::read_volatile(IRQ_PEND_2) };
-				writeln!(&mut uart1, "  - IRQ 2: {:b}", irq2).unwrap();
-			}
-		}
-		2 | 6 | 10 | 14 => {
-			// FIQ
 		}
-		3 | 7 | 11 | 15 => {
-			// SError
-		}
-		_ => unreachable!(),
+		ExceptionType::Irq => unreachable!(),
+		ExceptionType::Fiq => {}
+		ExceptionType::SError => {}
 	}
-	writeln!(&mut uart1, "Exception ended.").unwrap();
+	eprintln!("Exception ended.");
 }
 
-pub fn setup_interrupts(console: &mut Uart1) {
+pub fn setup_interrupts() {
 	let vbar = unsafe { core::ptr::addr_of!(__int_vec_base) };
 	// unsafe {
 	// 	asm!("ldr {}, __interrupt_vector", out(reg) vbar);
 	// }
-	writeln!(console, "Interrupt vector base: {:p}", vbar).unwrap();
+	debug!("Interrupt vector base: {:p}", vbar);
 	let res = vbar as u64 & 0b11111111111;
 	if res != 0 {
-		writeln!(
-			console,
+		error!(
 			"The interrupt vector ({:p}) isn't properly aligned: {:b}",
 			vbar as *const u8, res
-		)
-		.unwrap();
+		);
 	} else {
-		writeln!(console, "Interrupt vec is properly aligned.").unwrap();
+		debug!("Interrupt vec is properly aligned.");
 	}
-	unsafe {
-		// Set the Vector base into the VBAR
-		asm!(
-			"msr VBAR_EL3, {0}",
-			"msr VBAR_EL2, {0}",
-			"msr VBAR_EL1, {0}",
-			in(reg) vbar
-		);
-
-		// Setup interrupt routing: SError / Abort, FIQ, and IRQ should be taken and routed to EL3
-		asm!(
-			"mrs x8, SCR_EL3",
-			"orr x8, x8, {}",
-			"msr SCR_EL3, x8",
-			const 0b1110,
-			out("x8") _
-		);
+	setup_core_interrupts();
+}
 
-		// Unmask the IRQs that we're allowed to access (The others are only for the GPU)
+// Install the vector table and unmask interrupts on the calling core.  Secondary cores need to call this themselves before they can take any exceptions.
+pub fn setup_core_interrupts() {
+	let vbar = unsafe { core::ptr::addr_of!(__int_vec_base) };
+	unsafe {
+		// Set the Vector base into the VBAR.  Interrupt routing to the kernel's EL was already setup when we left EL3 (see grit.rs).
+		asm!(concat!("msr VBAR_", el!(), ", {}"), in(reg) vbar);
 
-		// Unmask all interrupts (Interrupts are bits 9-6; 0 is unmasked.)
+		// Unmask all interrupts (Interrupts are bits 9-6; 0 is unmasked.)  Individual sources are enabled in the interrupt *controller* by register_handler.
 		let mask = 0b0000 << 6;
 		asm!("msr DAIF, {:x}", in(reg) mask);
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IrqSource {
+	// One of the 64 GPU peripheral interrupts: bits of IRQ_PEND_1 (0-31) and IRQ_PEND_2 (32-63)
+	Gpu(u8),
+	// One of the 8 ARM interrupts in bits 0-7 of IRQ_PEND_BASIC: 0 is the ARM timer, 1 the ARM mailbox, 2/3 the doorbells, 4/5 GPU halted, 6/7 illegal access
+	Basic(u8),
+	// One of the calling core's generic timers, which come through the ARM local interrupt controller instead of the GPU's
+	CoreTimer(Timer),
+	// One of the calling core's 4 mailboxes, which raise an IRQ while they're non-zero.  The handler has to clear the mailbox (local_intc::take).
+	Mailbox(u8),
+}
+impl IrqSource {
+	fn from_index(i: usize) -> Self {
+		match i {
+			0..=63 => Self::Gpu(i as u8),
+			64..=71 => Self::Basic(i as u8 - 64),
+			72..=75 => Self::CoreTimer(CORE_TIMERS[i - 72]),
+			_ => Self::Mailbox(i as u8 - 76),
+		}
+	}
+	fn index(self) -> usize {
+		match self {
+			Self::Gpu(n) => {
+				assert!(n < 64);
+				n as usize
+			}
+			Self::Basic(n) => {
+				assert!(n < 8);
+				64 + n as usize
+			}
+			Self::CoreTimer(timer) => 72 + timer as usize,
+			Self::Mailbox(n) => {
+				assert!((n as usize) < local_intc::MAILBOXES);
+				76 + n as usize
+			}
+		}
+	}
+	// The enable / disable register for this source and the bit in it.  The enable / disable registers are write 1 to set / clear, so there's no read modify write.
+	fn control(self, enable: bool) -> (*mut u32, u32) {
+		match (self, enable) {
+			(Self::Gpu(n), true) if n < 32 => (IRQ_ENABLE_1, 1 << n),
+			(Self::Gpu(n), true) => (IRQ_ENABLE_2, 1 << (n - 32)),
+			(Self::Basic(n), true) => (IRQ_ENABLE_BASIC, 1 << n),
+			(Self::Gpu(n), false) if n < 32 => (IRQ_DISABLE_1, 1 << n),
+			(Self::Gpu(n), false) => (IRQ_DISABLE_2, 1 << (n - 32)),
+			(Self::Basic(n), false) => (IRQ_DISABLE_BASIC, 1 << n),
+			(Self::CoreTimer(_), _) | (Self::Mailbox(_), _) => unreachable!(),
+		}
+	}
+}
+
+// Handlers can capture state, so they're boxed.  Sync because any core can take the interrupt.
+pub type IrqHandler = dyn Fn() + Send + Sync;
+
+const IRQ_SOURCES: usize = 64 + 8 + 4 + 4;
+// Pointers to leaked Box<Box<IrqHandler>>s (the outer box makes them thin) stored as usize so that registering a handler is a single atomic store.  0 means no handler.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static IRQ_HANDLERS: [AtomicUsize; IRQ_SOURCES] = [NO_HANDLER; IRQ_SOURCES];
+// How many times each source has been dispatched, across all the cores
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_IRQS: AtomicUsize = AtomicUsize::new(0);
+static IRQ_COUNTS: [AtomicUsize; IRQ_SOURCES] = [NO_IRQS; IRQ_SOURCES];
 
-		// Enable all the basic interrupts in the interrupt *controller*
-		core::ptr::write_volatile(IRQ_ENABLE_BASIC, !0b11111111);
+// Bits 10-20 of IRQ_PEND_BASIC are shortcuts to these GPU interrupts, and those interrupts don't set the "pending register 1/2" bits (8 and 9).
+const BASIC_SHORTCUTS: [u8; 11] = [7, 9, 10, 18, 19, 53, 54, 55, 56, 57, 62];
+const BASIC_PEND_1: u32 = 1 << 8;
+const BASIC_PEND_2: u32 = 1 << 9;
 
-		// Enable Sytem Timer Match IRQ 1
-		core::ptr::write_volatile(IRQ_ENABLE_1, 0b10);
+const CORE_TIMERS: [Timer; 4] = [
+	Timer::SecurePhysical,
+	Timer::Physical,
+	Timer::Hypervisor,
+	Timer::Virtual,
+];
+
+// Set the handler for an interrupt source and enable it in the interrupt controller.  This replaces any handler that was already registered.  Another core could still be running the old handler, so it's leaked instead of freed: register once and use enable_irq / disable_irq after that.
+pub fn register_handler(source: IrqSource, handler: impl Fn() + Send + Sync + 'static) {
+	debug!("Handling {:?}", source);
+	let handler: Box<Box<IrqHandler>> = Box::new(Box::new(handler));
+	IRQ_HANDLERS[source.index()].store(Box::into_raw(handler) as usize, Ordering::Release);
+	enable_irq(source);
+}
+
+// Like register_handler, this leaks the old handler.
+#[allow(unused)]
+pub fn unregister_handler(source: IrqSource) {
+	disable_irq(source);
+	IRQ_HANDLERS[source.index()].store(0, Ordering::Release);
+}
+
+// Core timers and mailboxes are only enabled / disabled for the calling core.
+pub fn enable_irq(source: IrqSource) {
+	set_irq(source, true);
+}
+
+pub fn disable_irq(source: IrqSource) {
+	set_irq(source, false);
+}
+
+fn set_irq(source: IrqSource, enable: bool) {
+	match source {
+		IrqSource::CoreTimer(timer) => local_intc::enable_timer_irq(timer, enable),
+		IrqSource::Mailbox(n) => local_intc::enable_mailbox_irq(n as usize, enable),
+		_ => {
+			let (reg, bit) = source.control(enable);
+			unsafe { core::ptr::write_volatile(reg, bit) };
+		}
+	}
+}
+
+fn dispatch(source: IrqSource) {
+	trace!("IRQ {:?}", source);
+	IRQ_COUNTS[source.index()].fetch_add(1, Ordering::Relaxed);
+	let handler = IRQ_HANDLERS[source.index()].load(Ordering::Acquire);
+	if handler != 0 {
+		let handler = unsafe { &*(handler as *const Box<IrqHandler>) };
+		handler();
+	} else {
+		// Nobody owns this interrupt, so turn it off instead of taking it forever.
+		disable_irq(source);
+		warn!("Disabled unhandled IRQ: {:?}", source);
+	}
+}
+
+fn dispatch_irqs() {
+	let pending = local_intc::pending_irqs();
+	for timer in CORE_TIMERS.iter() {
+		if pending.timer(*timer) {
+			dispatch(IrqSource::CoreTimer(*timer));
+		}
+	}
+	for n in 0..local_intc::MAILBOXES {
+		if pending.mailbox(n) {
+			dispatch(IrqSource::Mailbox(n as u8));
+		}
 	}
+	if pending.gpu() {
+		dispatch_gpu_irqs();
+	}
+}
+
+fn dispatch_gpu_irqs() {
+	let basic = unsafe { core::ptr::read_volatile(IRQ_PEND_BASIC) };
+	for n in 0..8 {
+		if basic & (1 << n) != 0 {
+			dispatch(IrqSource::Basic(n));
+		}
+	}
+
+	// Gather every pending GPU interrupt first so that one which is both a shortcut and in a pending register is only dispatched once.
+	let mut gpu = 0u64;
+	for (i, n) in BASIC_SHORTCUTS.iter().enumerate() {
+		if basic & (1 << (10 + i)) != 0 {
+			gpu |= 1 << n;
+		}
+	}
+	if basic & BASIC_PEND_1 != 0 {
+		gpu |= unsafe { core::ptr::read_volatile(IRQ_PEND_1) } as u64;
+	}
+	if basic & BASIC_PEND_2 != 0 {
+		gpu |= (unsafe { core::ptr::read_volatile(IRQ_PEND_2) } as u64) << 32;
+	}
+	while gpu != 0 {
+		let n = gpu.trailing_zeros();
+		gpu &= !(1 << n);
+		dispatch(IrqSource::Gpu(n as u8));
+	}
+}
+
+fn irqs(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	if !args.is_empty() {
+		return Err(CommandError::Usage);
+	}
+	for i in 0..IRQ_SOURCES {
+		let handled = IRQ_HANDLERS[i].load(Ordering::Acquire) != 0;
+		let count = IRQ_COUNTS[i].load(Ordering::Relaxed);
+		if handled || count != 0 {
+			writeln!(
+				out,
+				"{:<24}{:>10}{}",
+				alloc::format!("{:?}", IrqSource::from_index(i)),
+				count,
+				if handled { "" } else { " (no handler)" }
+			)?;
+		}
+	}
+	Ok(())
+}
+
+pub static COMMANDS: [Command; 1] = [Command {
+	name: "irqs",
+	args: "",
+	help: "How many times each interrupt has fired",
+	run: irqs,
+}];
+
+// The shared half of every vector stub.  The stub has already made room for the TrapFrame, saved x0 and x1, and put its VectorKind in x1.
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn int_common() {
+	asm!(
+		// Store the rest of the general purpose registers
+		"stp x2, x3, [sp, #16]",
+		"stp x4, x5, [sp, #32]",
+		"stp x6, x7, [sp, #48]",
+		"stp x8, x9, [sp, #64]",
+		"stp x10, x11, [sp, #80]",
+		"stp x12, x13, [sp, #96]",
+		"stp x14, x15, [sp, #112]",
+		"stp x16, x17, [sp, #128]",
+		"stp x18, x19, [sp, #144]",
+		"stp x20, x21, [sp, #160]",
+		"stp x22, x23, [sp, #176]",
+		"stp x24, x25, [sp, #192]",
+		"stp x26, x27, [sp, #208]",
+		"stp x28, x29, [sp, #224]",
+		"str x30, [sp, #240]",
+		// The interrupted code was on SP_ELx only if it was at our EL and not using SP0.  Otherwise it was on SP_EL0.
+		"add x2, sp, #{frame_size}",
+		"cmp x1, #{spx_first}",
+		"b.lo 1f",
+		"cmp x1, #{lower_first}",
+		"b.lo 2f",
+		"1:",
+		"mrs x2, SP_EL0",
+		"2:",
+		concat!("mrs x3, ELR_", el!()),
+		"stp x2, x3, [sp, #248]",
+		concat!("mrs x4, SPSR_", el!()),
+		concat!("mrs x5, ESR_", el!()),
+		"stp x4, x5, [sp, #264]",
+		concat!("mrs x6, FAR_", el!()),
+		"str x6, [sp, #280]",
+		// Call the Rust interrupt handler with the frame and the vector kind.  x19 is callee saved, so the kind survives the call.
+		"mov x19, x1",
+		"mov x0, sp",
+		"bl {handler}",
+		// Restore the (possibly modified) interrupted context
+		"ldp x2, x3, [sp, #248]",
+		"ldr x4, [sp, #264]",
+		concat!("msr ELR_", el!(), ", x3"),
+		concat!("msr SPSR_", el!(), ", x4"),
+		"cmp x19, #{spx_first}",
+		"b.lo 3f",
+		"cmp x19, #{lower_first}",
+		"b.lo 4f",
+		"3:",
+		"msr SP_EL0, x2",
+		"4:",
+		"ldp x0, x1, [sp, #0]",
+		"ldp x2, x3, [sp, #16]",
+		"ldp x4, x5, [sp, #32]",
+		"ldp x6, x7, [sp, #48]",
+		"ldp x8, x9, [sp, #64]",
+		"ldp x10, x11, [sp, #80]",
+		"ldp x12, x13, [sp, #96]",
+		"ldp x14, x15, [sp, #112]",
+		"ldp x16, x17, [sp, #128]",
+		"ldp x18, x19, [sp, #144]",
+		"ldp x20, x21, [sp, #160]",
+		"ldp x22, x23, [sp, #176]",
+		"ldp x24, x25, [sp, #192]",
+		"ldp x26, x27, [sp, #208]",
+		"ldp x28, x29, [sp, #224]",
+		"ldr x30, [sp, #240]",
+		"add sp, sp, #{frame_size}",
+		// Return from the exception
+		"eret",
+		frame_size = const FRAME_SIZE,
+		spx_first = const VectorKind::CurrentSpxSync as u64,
+		lower_first = const VectorKind::Lower64Sync as u64,
+		handler = sym interrupt_handler,
+		options(noreturn)
+	);
 }
 
+// Each entry in the vector table only has room for 32 instructions, so the stubs just save enough to identify themselves and jump to int_common.
 macro_rules! make_interrupt {
-	($function_name:ident, $handler_name:literal) => {
+	($function_name:ident, $kind:expr) => {
 		#[link_section = concat!(".int_vec.", stringify!($function_name))]
 		#[no_mangle]
 		#[naked]
 		pub unsafe extern "C" fn $function_name() {
 			asm!(
-				// Store corruptible registers
-				"stp x0, x1, [sp, #-16]!",
-				"stp x2, x3, [sp, #-16]!",
-				"stp x4, x5, [sp, #-16]!",
-				"stp x6, x7, [sp, #-16]!",
-				"stp x8, x9, [sp, #-16]!",
-				"stp x10, x11, [sp, #-16]!",
-				"stp x12, x13, [sp, #-16]!",
-				"stp x14, x15, [sp, #-16]!",
-				"stp x16, x17, [sp, #-16]!",
-				"stp x18, x19, [sp, #-16]!",
-				"stp x29, x30, [sp, #-16]!",
-				// Call the Rust interrupt handler
-				concat!("bl ", $handler_name),
-				// Restore corruptible registers
-				"ldp x29, x30, [sp], #16",
-				"ldp x18, x19, [sp], #16",
-				"ldp x16, x17, [sp], #16",
-				"ldp x14, x15, [sp], #16",
-				"ldp x12, x13, [sp], #16",
-				"ldp x10, x11, [sp], #16",
-				"ldp x8, x9, [sp], #16",
-				"ldp x6, x7, [sp], #16",
-				"ldp x4, x5, [sp], #16",
-				"ldp x2, x3, [sp], #16",
-				"ldp x0, x1, [sp], #16",
-				// Return from the exception
-				"eret",
+				"sub sp, sp, #{frame_size}",
+				"stp x0, x1, [sp, #0]",
+				"mov x1, #{kind}",
+				"b {common}",
+				frame_size = const FRAME_SIZE,
+				kind = const $kind as u64,
+				common = sym int_common,
 				options(noreturn)
 			);
 		}
@@ -184,22 +441,22 @@ macro_rules! make_interrupt {
 }
 
 // Current Exception level - Stack 0
-make_interrupt!(int_sync_sp0, "interrupt_handler");
-make_interrupt!(int_irq_sp0, "interrupt_handler");
-make_interrupt!(int_fiq_sp0, "interrupt_handler");
-make_interrupt!(int_serr_sp0, "interrupt_handler");
+make_interrupt!(int_sync_sp0, VectorKind::CurrentSp0Sync);
+make_interrupt!(int_irq_sp0, VectorKind::CurrentSp0Irq);
+make_interrupt!(int_fiq_sp0, VectorKind::CurrentSp0Fiq);
+make_interrupt!(int_serr_sp0, VectorKind::CurrentSp0SError);
 // Current Exception level - Stack x
-make_interrupt!(int_sync_spx, "interrupt_handler");
-make_interrupt!(int_irq_spx, "interrupt_handler");
-make_interrupt!(int_fiq_spx, "interrupt_handler");
-make_interrupt!(int_serr_spx, "interrupt_handler");
+make_interrupt!(int_sync_spx, VectorKind::CurrentSpxSync);
+make_interrupt!(int_irq_spx, VectorKind::CurrentSpxIrq);
+make_interrupt!(int_fiq_spx, VectorKind::CurrentSpxFiq);
+make_interrupt!(int_serr_spx, VectorKind::CurrentSpxSError);
 // Lower Exception level - aarch64
-make_interrupt!(int_sync_lel64, "interrupt_handler");
-make_interrupt!(int_irq_lel64, "interrupt_handler");
-make_interrupt!(int_fiq_lel64, "interrupt_handler");
-make_interrupt!(int_serr_lel64, "interrupt_handler");
+make_interrupt!(int_sync_lel64, VectorKind::Lower64Sync);
+make_interrupt!(int_irq_lel64, VectorKind::Lower64Irq);
+make_interrupt!(int_fiq_lel64, VectorKind::Lower64Fiq);
+make_interrupt!(int_serr_lel64, VectorKind::Lower64SError);
 // Lower Exception level - aarch32
-make_interrupt!(int_sync_lel32, "interrupt_handler");
-make_interrupt!(int_irq_lel32, "interrupt_handler");
-make_interrupt!(int_fiq_lel32, "interrupt_handler");
-make_interrupt!(int_serr_lel32, "interrupt_handler");
+make_interrupt!(int_sync_lel32, VectorKind::Lower32Sync);
+make_interrupt!(int_irq_lel32, VectorKind::Lower32Irq);
+make_interrupt!(int_fiq_lel32, VectorKind::Lower32Fiq);
+make_interrupt!(int_serr_lel32, VectorKind::Lower32SError);
@@ -0,0 +1,108 @@
+#![allow(dead_code)]
+
+use super::{cpu, generic_timer::Timer, memory::local::*};
+use core::ptr;
+
+pub const MAILBOXES: usize = 4;
+
+// The sources in a core's IRQ / FIQ source register.  Bits 0-3 are the generic timers (in the same order as Timer) and 4-7 are the core's mailboxes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pending(pub u32);
+impl Pending {
+	const GPU: u32 = 1 << 8;
+	const PMU: u32 = 1 << 9;
+	const LOCAL_TIMER: u32 = 1 << 11;
+
+	pub fn timer(self, timer: Timer) -> bool {
+		self.0 & (1 << timer as u32) != 0
+	}
+	pub fn mailbox(self, mailbox: usize) -> bool {
+		assert!(mailbox < MAILBOXES);
+		self.0 & (1 << (4 + mailbox)) != 0
+	}
+	// Something in the GPU's interrupt controller (see interrupts.rs) is pending.  Only one core gets these, see route_gpu_irqs.
+	pub fn gpu(self) -> bool {
+		self.0 & Self::GPU != 0
+	}
+	pub fn pmu(self) -> bool {
+		self.0 & Self::PMU != 0
+	}
+	pub fn local_timer(self) -> bool {
+		self.0 & Self::LOCAL_TIMER != 0
+	}
+}
+
+// What is raising an IRQ on the calling core
+pub fn pending_irqs() -> Pending {
+	Pending(unsafe { ptr::read_volatile(CORE_IRQ_SOURCE.add(cpu::core_id())) })
+}
+
+pub fn pending_fiqs() -> Pending {
+	Pending(unsafe { ptr::read_volatile(CORE_FIQ_SOURCE.add(cpu::core_id())) })
+}
+
+// Send all GPU IRQs to one core.  Bits 0-1 pick the IRQ core and bits 2-3 the FIQ core.
+pub fn route_gpu_irqs(core: usize) {
+	assert!(core < cpu::CORE_COUNT);
+	unsafe {
+		let routing = ptr::read_volatile(GPU_INT_ROUTING);
+		ptr::write_volatile(GPU_INT_ROUTING, (routing & !0b11) | core as u32);
+	}
+}
+
+pub fn gpu_irq_core() -> usize {
+	(unsafe { ptr::read_volatile(GPU_INT_ROUTING) } & 0b11) as usize
+}
+
+// The per-core control registers aren't write 1 to set / clear like the GPU's, so they need a read modify write.  Only the calling core touches its own register, so there's no race.
+fn set_core_ctl(reg: *mut u32, bit: u32, enable: bool) {
+	let reg = unsafe { reg.add(cpu::core_id()) };
+	unsafe {
+		let ctl = ptr::read_volatile(reg);
+		let ctl = if enable { ctl | bit } else { ctl & !bit };
+		ptr::write_volatile(reg, ctl);
+	}
+}
+
+// Let a generic timer raise an IRQ on the calling core
+pub fn enable_timer_irq(timer: Timer, enable: bool) {
+	set_core_ctl(CORE_TIMER_INT_CTL, 1 << timer as u32, enable);
+}
+
+// Let a non-zero mailbox raise an IRQ on the calling core
+pub fn enable_mailbox_irq(mailbox: usize, enable: bool) {
+	assert!(mailbox < MAILBOXES);
+	set_core_ctl(CORE_MAILBOX_INT_CTL, 1 << mailbox, enable);
+}
+
+fn mailbox_index(core: usize, mailbox: usize) -> usize {
+	assert!(core < cpu::CORE_COUNT && mailbox < MAILBOXES);
+	core * MAILBOXES + mailbox
+}
+
+// Set bits in another core's mailbox.  If that core has the mailbox's IRQ enabled, this is an IPI.
+pub fn send(core: usize, mailbox: usize, bits: u32) {
+	unsafe { ptr::write_volatile(CORE_MAILBOX_SET.add(mailbox_index(core, mailbox)), bits) };
+}
+
+// Read one of the calling core's mailboxes
+pub fn read(mailbox: usize) -> u32 {
+	unsafe { ptr::read_volatile(CORE_MAILBOX_CLEAR.add(mailbox_index(cpu::core_id(), mailbox))) }
+}
+
+// Clear bits in one of the calling core's mailboxes.  The mailbox keeps raising its IRQ until every bit is clear.
+pub fn clear(mailbox: usize, bits: u32) {
+	unsafe {
+		ptr::write_volatile(
+			CORE_MAILBOX_CLEAR.add(mailbox_index(cpu::core_id(), mailbox)),
+			bits,
+		)
+	};
+}
+
+// Read and clear a mailbox in one go, for handlers.
+pub fn take(mailbox: usize) -> u32 {
+	let bits = read(mailbox);
+	clear(mailbox, bits);
+	bits
+}
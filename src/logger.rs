@@ -0,0 +1,563 @@
+#![allow(dead_code)]
+
+// The log crate backend.  Every record gets the system timer timestamp and the core it came from, then goes to each sink that wants its level.
+//
+// Filtering happens in three places:
+// - The log crate's max_level_* features, which compile out everything above a level for the whole kernel
+// - DEFAULT_FILTERS, which are per module and baked in at compile time
+// - Runtime overrides (set_filter, or the log shell command), which win over the defaults
+use super::{
+	ring_buffer::RingBuffer,
+	shell::{Command, CommandError},
+	sync::SpinLock,
+};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{
+	cell::UnsafeCell,
+	fmt::{self, Write},
+	sync::atomic::{AtomicBool, Ordering},
+};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Anything that isn't covered by a filter
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+// Interrupt tracing logs every IRQ, so it's compiled in but off.  Turn it on with: log baremetal_pi::interrupts trace, and read it with dmesg.  The console only gets debug and up, because sending an IRQ's trace line out of the UART causes another UART IRQ.
+const DEFAULT_FILTERS: &[(&str, LevelFilter)] = &[("baremetal_pi::interrupts", LevelFilter::Info)];
+const CONSOLE_LEVEL: LevelFilter = LevelFilter::Debug;
+// Records longer than this get cut off
+const LINE_LEN: usize = 256;
+const RING_SIZE: usize = 16 * 1024;
+const MAX_SINKS: usize = 4;
+// Lines waiting for a busy sink.  More than this and new lines are dropped.
+const PENDING_SIZE: usize = 2048;
+
+// Somewhere for log lines to go.  Lines come fully formatted, with the newline.
+pub trait Sink: Send {
+	fn write_line(&mut self, line: &str);
+}
+
+// Module filters.  The most specific module that a record's target is in decides, and overrides beat defaults.
+pub struct Filters {
+	defaults: &'static [(&'static str, LevelFilter)],
+	overrides: Vec<(String, LevelFilter)>,
+	default: LevelFilter,
+}
+impl Filters {
+	pub const fn new(
+		defaults: &'static [(&'static str, LevelFilter)],
+		default: LevelFilter,
+	) -> Self {
+		Self {
+			defaults,
+			overrides: Vec::new(),
+			default,
+		}
+	}
+	pub fn level_for(&self, target: &str) -> LevelFilter {
+		let overrides = self.overrides.iter().map(|(m, l)| (m.as_str(), *l));
+		let defaults = self.defaults.iter().copied();
+		let mut best: Option<(&str, LevelFilter)> = None;
+		for (module, level) in overrides.chain(defaults) {
+			// On a tie, the first one (the override) wins
+			if in_module(target, module) && best.map_or(true, |(b, _)| module.len() > b.len()) {
+				best = Some((module, level));
+			}
+		}
+		best.map_or(self.default, |(_, level)| level)
+	}
+	pub fn set(&mut self, module: &str, level: LevelFilter) {
+		match self.overrides.iter_mut().find(|(m, _)| m == module) {
+			Some(filter) => filter.1 = level,
+			None => self.overrides.push((module.into(), level)),
+		}
+	}
+	pub fn set_default(&mut self, level: LevelFilter) {
+		self.default = level;
+	}
+	// The most verbose level that anything could log at.  The log crate checks this before it calls us, so it keeps disabled trace! calls cheap.
+	pub fn max_level(&self) -> LevelFilter {
+		let overrides = self.overrides.iter().map(|(_, l)| *l);
+		let defaults = self
+			.defaults
+			.iter()
+			.filter(|(module, _)| !self.is_overridden(module))
+			.map(|(_, l)| *l);
+		overrides
+			.chain(defaults)
+			.fold(self.default, |max, level| max.max(level))
+	}
+	fn is_overridden(&self, module: &str) -> bool {
+		self.overrides.iter().any(|(m, _)| m == module)
+	}
+	fn write_to(&self, out: &mut dyn Write) -> fmt::Result {
+		writeln!(out, "(default) {}", self.default)?;
+		for (module, level) in self.defaults.iter() {
+			if !self.is_overridden(module) {
+				writeln!(out, "{} {}", module, level)?;
+			}
+		}
+		for (module, level) in self.overrides.iter() {
+			writeln!(out, "{} {} (set at runtime)", module, level)?;
+		}
+		Ok(())
+	}
+}
+
+// target is in module if it's module itself or one of its submodules
+fn in_module(target: &str, module: &str) -> bool {
+	match target.strip_prefix(module) {
+		Some(rest) => rest.is_empty() || rest.starts_with("::"),
+		None => false,
+	}
+}
+
+// A fixed size line, so that formatting a record doesn't need the heap (or its lock)
+struct Line {
+	buf: [u8; LINE_LEN],
+	len: usize,
+}
+impl Line {
+	fn new() -> Self {
+		Self {
+			buf: [0; LINE_LEN],
+			len: 0,
+		}
+	}
+	fn as_str(&self) -> &str {
+		// write_str only cuts between characters
+		core::str::from_utf8(&self.buf[..self.len]).unwrap()
+	}
+}
+impl Write for Line {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		// Always leave room for the newline, and don't split a character
+		let mut len = s.len().min(LINE_LEN - 1 - self.len);
+		while !s.is_char_boundary(len) {
+			len -= 1;
+		}
+		self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+		self.len += len;
+		Ok(())
+	}
+}
+
+// [seconds.micros core LEVEL target] message
+fn format_record(
+	line: &mut Line,
+	micros: u64,
+	core: usize,
+	level: Level,
+	target: &str,
+	args: &fmt::Arguments<'_>,
+) {
+	let _ = write!(
+		line,
+		"[{:>5}.{:06} {} {:<5} {}] {}",
+		micros / 1_000_000,
+		micros % 1_000_000,
+		core,
+		level,
+		target,
+		args
+	);
+	line.buf[line.len] = b'\n';
+	line.len += 1;
+}
+
+#[cfg(target_arch = "aarch64")]
+fn timestamp() -> u64 {
+	super::timer::now().as_micros()
+}
+#[cfg(target_arch = "aarch64")]
+fn this_core() -> usize {
+	super::cpu::core_id()
+}
+#[cfg(not(target_arch = "aarch64"))]
+fn timestamp() -> u64 {
+	0
+}
+#[cfg(not(target_arch = "aarch64"))]
+fn this_core() -> usize {
+	0
+}
+
+// Each sink has its own lock, so a slow sink (drawing on the framebuffer) doesn't hold up the others.  That lock doesn't mask interrupts either: a line that comes in while the sink is busy (from an IRQ that interrupted it, or from another core) gets queued, and whoever has the sink writes it out before letting go.
+struct SinkSlot {
+	sink: UnsafeCell<Box<dyn Sink>>,
+	level: LevelFilter,
+	busy: AtomicBool,
+	// Pushed with push_lock held, and popped by whoever set busy
+	pending: RingBuffer<PENDING_SIZE>,
+	push_lock: SpinLock<()>,
+}
+unsafe impl Sync for SinkSlot {}
+impl SinkSlot {
+	fn new(sink: Box<dyn Sink>, level: LevelFilter) -> Self {
+		Self {
+			sink: UnsafeCell::new(sink),
+			level,
+			busy: AtomicBool::new(false),
+			pending: RingBuffer::new(),
+			push_lock: SpinLock::new(()),
+		}
+	}
+	fn write_line(&self, line: &str) {
+		{
+			let _guard = self.push_lock.lock();
+			// Whole lines or nothing, so that the lines that are popped are whole
+			if PENDING_SIZE - self.pending.len() < line.len() {
+				return;
+			}
+			for b in line.bytes() {
+				self.pending.push(b);
+			}
+		}
+		while !self.pending.is_empty() {
+			if self.busy.swap(true, Ordering::Acquire) {
+				// Whoever has the sink will get to our line
+				return;
+			}
+			let sink = unsafe { &mut *self.sink.get() };
+			let mut line = Line::new();
+			while let Some(b) = self.pending.pop() {
+				line.buf[line.len] = b;
+				line.len += 1;
+				if b == b'\n' {
+					sink.write_line(line.as_str());
+					line.len = 0;
+				}
+			}
+			// Something could have been queued after the last pop, so check again after letting go
+			self.busy.store(false, Ordering::Release);
+		}
+	}
+}
+
+struct Logger {
+	filters: SpinLock<Filters>,
+	// This lock is only held long enough to copy the list out
+	sinks: SpinLock<[Option<&'static SinkSlot>; MAX_SINKS]>,
+}
+impl Log for Logger {
+	fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+		metadata.level() <= self.filters.lock().level_for(metadata.target())
+	}
+	fn log(&self, record: &Record<'_>) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+		let mut line = Line::new();
+		format_record(
+			&mut line,
+			timestamp(),
+			this_core(),
+			record.level(),
+			record.target(),
+			record.args(),
+		);
+		let sinks = *self.sinks.lock();
+		for slot in sinks.iter().flatten() {
+			if record.level() <= slot.level {
+				slot.write_line(line.as_str());
+			}
+		}
+	}
+	fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger {
+	filters: SpinLock::new(Filters::new(DEFAULT_FILTERS, DEFAULT_LEVEL)),
+	sinks: SpinLock::new([None; MAX_SINKS]),
+};
+
+// Install the logger, sending everything to the console and the in memory ring.  This needs the heap.
+pub fn init() {
+	log::set_logger(&LOGGER).expect("The logger was already set");
+	log::set_max_level(LOGGER.filters.lock().max_level());
+	add_sink(Box::new(ConsoleSink), CONSOLE_LEVEL);
+	add_sink(Box::new(RingSink), LevelFilter::Trace);
+}
+
+// sink gets every record at level or below (that made it past the filters)
+pub fn add_sink(sink: Box<dyn Sink>, level: LevelFilter) {
+	let slot = Box::leak(Box::new(SinkSlot::new(sink, level)));
+	let mut sinks = LOGGER.sinks.lock();
+	let free = sinks
+		.iter_mut()
+		.find(|s| s.is_none())
+		.expect("There's no room for another log sink");
+	*free = Some(slot);
+}
+
+pub fn set_filter(module: &str, level: LevelFilter) {
+	let mut filters = LOGGER.filters.lock();
+	filters.set(module, level);
+	log::set_max_level(filters.max_level());
+}
+
+pub fn set_default_level(level: LevelFilter) {
+	let mut filters = LOGGER.filters.lock();
+	filters.set_default(level);
+	log::set_max_level(filters.max_level());
+}
+
+pub struct ConsoleSink;
+impl Sink for ConsoleSink {
+	fn write_line(&mut self, line: &str) {
+		let _ = super::console::lock().write_str(line);
+	}
+}
+
+impl Sink for super::fb_console::FbConsole {
+	fn write_line(&mut self, line: &str) {
+		let _ = self.write_str(line);
+	}
+}
+
+// The last RING_SIZE bytes of log output, for dmesg.  Old lines get overwritten.
+pub struct LogRing<const N: usize> {
+	buf: [u8; N],
+	// Where the next byte goes
+	head: usize,
+	full: bool,
+}
+impl<const N: usize> LogRing<N> {
+	pub const fn new() -> Self {
+		Self {
+			buf: [0; N],
+			head: 0,
+			full: false,
+		}
+	}
+	pub fn push(&mut self, bytes: &[u8]) {
+		for &b in bytes {
+			self.buf[self.head] = b;
+			self.head = (self.head + 1) % N;
+			if self.head == 0 {
+				self.full = true;
+			}
+		}
+	}
+	// Oldest first, in two parts because it wraps
+	pub fn contents(&self) -> (&[u8], &[u8]) {
+		if self.full {
+			(&self.buf[self.head..], &self.buf[..self.head])
+		} else {
+			(&self.buf[..self.head], &[])
+		}
+	}
+	// The contents, minus the partial line that's left after old output got overwritten
+	pub fn lines(&self) -> impl Iterator<Item = &[u8]> {
+		let (first, second) = self.contents();
+		let skip = if self.full {
+			first
+				.iter()
+				.chain(second.iter())
+				.position(|&b| b == b'\n')
+				.map_or(N, |i| i + 1)
+		} else {
+			0
+		};
+		let (first, second) = if skip < first.len() {
+			(&first[skip..], second)
+		} else {
+			(&[][..], &second[(skip - first.len()).min(second.len())..])
+		};
+		IntoIterator::into_iter([first, second])
+	}
+}
+
+static RING: SpinLock<LogRing<RING_SIZE>> = SpinLock::new(LogRing::new());
+
+pub struct RingSink;
+impl Sink for RingSink {
+	fn write_line(&mut self, line: &str) {
+		RING.lock().push(line.as_bytes());
+	}
+}
+
+fn parse_level(s: &str) -> Result<LevelFilter, CommandError> {
+	s.parse()
+		.map_err(|_| CommandError::Failed("levels are off, error, warn, info, debug, and trace"))
+}
+
+fn log_command(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	match args {
+		[] => LOGGER.filters.lock().write_to(out)?,
+		[level] => set_default_level(parse_level(level)?),
+		[module, level] => set_filter(module, parse_level(level)?),
+		_ => return Err(CommandError::Usage),
+	}
+	Ok(())
+}
+
+fn dmesg(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	if !args.is_empty() {
+		return Err(CommandError::Usage);
+	}
+	// Copy it out so we're not holding the lock (and blocking logging) while the UART is busy
+	let mut copy = Vec::new();
+	for part in RING.lock().lines() {
+		copy.extend_from_slice(part);
+	}
+	out.write_str(&String::from_utf8_lossy(&copy))?;
+	Ok(())
+}
+
+pub static COMMANDS: [Command; 2] = [
+	Command {
+		name: "log",
+		args: "[module] [level]",
+		help: "Show the log filters, or set the level for a module (or everything)",
+		run: log_command,
+	},
+	Command {
+		name: "dmesg",
+		args: "",
+		help: "Show the recent log output",
+		run: dmesg,
+	},
+];
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+	use core::{ptr, sync::atomic::AtomicPtr};
+	use std::sync::{Arc, Mutex};
+
+	const TEST_FILTERS: &[(&str, LevelFilter)] = &[
+		("kernel::interrupts", LevelFilter::Warn),
+		("kernel::interrupts::gpu", LevelFilter::Trace),
+	];
+
+	#[test]
+	fn check_filters() {
+		let mut filters = Filters::new(TEST_FILTERS, LevelFilter::Info);
+		assert_eq!(filters.level_for("kernel"), LevelFilter::Info);
+		assert_eq!(filters.level_for("kernel::interrupts"), LevelFilter::Warn);
+		assert_eq!(
+			filters.level_for("kernel::interrupts::local"),
+			LevelFilter::Warn
+		);
+		assert_eq!(
+			filters.level_for("kernel::interrupts::gpu"),
+			LevelFilter::Trace
+		);
+		// Only whole module names match
+		assert_eq!(
+			filters.level_for("kernel::interrupts_old"),
+			LevelFilter::Info
+		);
+		assert_eq!(filters.max_level(), LevelFilter::Trace);
+
+		filters.set("kernel::interrupts::gpu", LevelFilter::Off);
+		filters.set("kernel", LevelFilter::Debug);
+		assert_eq!(
+			filters.level_for("kernel::interrupts::gpu"),
+			LevelFilter::Off
+		);
+		assert_eq!(filters.level_for("kernel::interrupts"), LevelFilter::Warn);
+		assert_eq!(filters.level_for("kernel::uart"), LevelFilter::Debug);
+		assert_eq!(filters.level_for("chainload"), LevelFilter::Info);
+		assert_eq!(filters.max_level(), LevelFilter::Debug);
+
+		filters.set("kernel", LevelFilter::Error);
+		filters.set_default(LevelFilter::Off);
+		assert_eq!(filters.level_for("kernel::uart"), LevelFilter::Error);
+		assert_eq!(filters.level_for("chainload"), LevelFilter::Off);
+		assert_eq!(filters.max_level(), LevelFilter::Warn);
+	}
+
+	#[test]
+	fn check_format_record() {
+		let mut line = Line::new();
+		format_record(
+			&mut line,
+			12_345_678,
+			2,
+			Level::Warn,
+			"kernel::uart",
+			&format_args!("Dropped {} bytes", 3),
+		);
+		assert_eq!(
+			line.as_str(),
+			"[   12.345678 2 WARN  kernel::uart] Dropped 3 bytes\n"
+		);
+
+		// Long records get cut off, but keep their newline
+		let mut line = Line::new();
+		let long = "x".repeat(500);
+		format_record(&mut line, 0, 0, Level::Info, "k", &format_args!("{}", long));
+		assert_eq!(line.as_str().len(), LINE_LEN);
+		assert!(line.as_str().ends_with("xx\n"));
+
+		// A character that doesn't fit is left off whole, and the newline is still there
+		let mut line = Line::new();
+		let long = "é".repeat(200);
+		format_record(
+			&mut line,
+			0,
+			0,
+			Level::Info,
+			"kk",
+			&format_args!("{}", long),
+		);
+		assert!(line.as_str().ends_with("é\n"));
+		assert!(line.as_str().len() > LINE_LEN - 3);
+	}
+
+	#[test]
+	fn check_log_ring() {
+		let mut ring = LogRing::<16>::new();
+		ring.push(b"one\ntwo\n");
+		let lines: Vec<u8> = ring.lines().flatten().copied().collect();
+		assert_eq!(lines, b"one\ntwo\n");
+
+		// Wraps, and whatever's left of "one" is dropped
+		ring.push(b"three\nfour\n");
+		assert_eq!(ring.contents(), (&b"\ntwo\nthree\nfo"[..], &b"ur\n"[..]));
+		let lines: Vec<u8> = ring.lines().flatten().copied().collect();
+		assert_eq!(lines, b"two\nthree\nfour\n");
+
+		// A line that's longer than the ring leaves nothing whole
+		let mut ring = LogRing::<16>::new();
+		ring.push(b"0123456789abcdefghi\n");
+		assert_eq!(ring.lines().flatten().count(), 0);
+		// And now the partial line ends in the second half
+		ring.push(b"ok\n");
+		let lines: Vec<u8> = ring.lines().flatten().copied().collect();
+		assert_eq!(lines, b"ok\n");
+	}
+
+	static NESTED_SLOT: AtomicPtr<SinkSlot> = AtomicPtr::new(ptr::null_mut());
+	struct Recorder(Arc<Mutex<Vec<String>>>);
+	impl Sink for Recorder {
+		fn write_line(&mut self, line: &str) {
+			self.0.lock().unwrap().push(line.into());
+			// Like an IRQ that logs while the sink is in the middle of a line
+			if line == "first\n" {
+				unsafe { &*NESTED_SLOT.load(Ordering::Relaxed) }.write_line("nested\n");
+			}
+		}
+	}
+
+	#[test]
+	fn check_sink_slot() {
+		let lines = Arc::new(Mutex::new(Vec::new()));
+		let sink = Box::new(Recorder(lines.clone()));
+		let slot: &'static SinkSlot = Box::leak(Box::new(SinkSlot::new(sink, LevelFilter::Trace)));
+		NESTED_SLOT.store(slot as *const _ as *mut _, Ordering::Relaxed);
+		slot.write_line("first\n");
+		slot.write_line("second\n");
+		// The nested line waits for the one it interrupted
+		assert_eq!(*lines.lock().unwrap(), ["first\n", "nested\n", "second\n"]);
+
+		// Lines that don't fit in the queue are dropped
+		slot.busy.store(true, Ordering::Relaxed);
+		let long = "x".repeat(LINE_LEN - 1) + "\n";
+		for _ in 0..PENDING_SIZE / LINE_LEN + 1 {
+			slot.write_line(&long);
+		}
+		assert_eq!(slot.pending.len(), PENDING_SIZE / LINE_LEN * LINE_LEN);
+	}
+}
@@ -0,0 +1,577 @@
+#![allow(dead_code)]
+
+use core::marker::PhantomData;
+
+#[cfg(target_arch = "aarch64")]
+use super::{cpu, memory::mailbox::*, sync::SpinLock};
+#[cfg(target_arch = "aarch64")]
+use core::{hint::spin_loop, ptr};
+
+// The property interface: a message is a list of tags that the firmware answers in place.
+// https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface
+pub const MESSAGE_WORDS: usize = 64;
+
+const REQUEST: u32 = 0;
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+// Set in a tag's request / response code once the firmware has answered it.  The rest is the length of the response.
+const TAG_RESPONSE: u32 = 1 << 31;
+const END_TAG: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MailboxError {
+	// The tags don't fit in MESSAGE_WORDS
+	BufferFull,
+	// The firmware didn't accept the message (the response code)
+	Failed(u32),
+	// The firmware didn't answer this tag, probably because it doesn't know it
+	Unanswered(u32),
+	// The response was bigger than the value buffer, so it was cut off
+	Truncated(u32),
+}
+
+pub trait Tag {
+	const ID: u32;
+	// Size of the value buffer in words.  It has to fit both the request and the response.
+	const VALUE_WORDS: usize;
+	type Response;
+	fn request(&self, _value: &mut [u32]) {}
+	fn response(value: &[u32]) -> Self::Response;
+}
+
+// Where a tag was put in a Message, for reading its response back out.
+pub struct TagRef<T> {
+	offset: usize,
+	tag: PhantomData<T>,
+}
+impl<T> Clone for TagRef<T> {
+	fn clone(&self) -> Self {
+		*self
+	}
+}
+impl<T> Copy for TagRef<T> {}
+
+// The firmware wants the buffer 16 byte aligned, because the low 4 bits of the address are the channel.  It's cache line aligned (and so a whole number of lines) on top of that, so that nothing else on the stack shares a line with the words while the cache is being cleaned and invalidated around the firmware.
+#[repr(C, align(64))]
+pub struct Message {
+	words: [u32; MESSAGE_WORDS],
+	len: usize,
+}
+
+impl Default for Message {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Message {
+	pub fn new() -> Self {
+		// Words 0 and 1 are the size and the request / response code
+		Self {
+			words: [0; MESSAGE_WORDS],
+			len: 2,
+		}
+	}
+
+	pub fn push<T: Tag>(&mut self, tag: &T) -> Result<TagRef<T>, MailboxError> {
+		let offset = self.len;
+		let value = offset + 3;
+		// Leave room for the end tag
+		if value + T::VALUE_WORDS + 1 > MESSAGE_WORDS {
+			return Err(MailboxError::BufferFull);
+		}
+		self.words[offset] = T::ID;
+		self.words[offset + 1] = (T::VALUE_WORDS * 4) as u32;
+		self.words[offset + 2] = REQUEST;
+		let value = &mut self.words[value..value + T::VALUE_WORDS];
+		value.fill(0);
+		tag.request(value);
+		self.len = offset + 3 + T::VALUE_WORDS;
+		Ok(TagRef {
+			offset,
+			tag: PhantomData,
+		})
+	}
+
+	// Write the end tag and the size, and return the words that make up the message.
+	pub fn finish(&mut self) -> &[u32] {
+		self.words[self.len] = END_TAG;
+		self.words[0] = ((self.len + 1) * 4) as u32;
+		self.words[1] = REQUEST;
+		&self.words[..=self.len]
+	}
+
+	pub fn get<T: Tag>(&self, tag: TagRef<T>) -> Result<T::Response, MailboxError> {
+		if self.words[1] != RESPONSE_SUCCESS {
+			return Err(MailboxError::Failed(self.words[1]));
+		}
+		let code = self.words[tag.offset + 2];
+		if code & TAG_RESPONSE == 0 {
+			return Err(MailboxError::Unanswered(T::ID));
+		}
+		if (code & !TAG_RESPONSE) as usize > T::VALUE_WORDS * 4 {
+			return Err(MailboxError::Truncated(T::ID));
+		}
+		let value = tag.offset + 3;
+		Ok(T::response(&self.words[value..value + T::VALUE_WORDS]))
+	}
+
+	// Hand the message to the firmware on the property channel and wait for it to answer.
+	#[cfg(target_arch = "aarch64")]
+	pub fn send(&mut self) -> Result<(), MailboxError> {
+		let len = self.finish().len() * 4;
+		let addr = self.words.as_mut_ptr() as usize;
+		// The GPU doesn't see our caches, so push the message out to memory, and give it the uncached bus address
+		cpu::clean_dcache(addr, len);
+		let message = (addr as u32 | BUS_UNCACHED) | CHANNEL_PROPERTY;
+
+		let _lock = MAILBOX.lock();
+		unsafe {
+			while ptr::read_volatile(MBOX_STATUS) & MBOX_FULL != 0 {
+				spin_loop();
+			}
+			ptr::write_volatile(MBOX_WRITE, message);
+			// Replies for other channels aren't ours, so skip them
+			loop {
+				while ptr::read_volatile(MBOX_STATUS) & MBOX_EMPTY != 0 {
+					spin_loop();
+				}
+				if ptr::read_volatile(MBOX_READ) == message {
+					break;
+				}
+			}
+		}
+		// Throw away anything that got pulled into the cache while the firmware was writing the response.  Only invalidate: cleaning would write those lines back over the response.
+		cpu::invalidate_dcache(addr, len);
+
+		match self.words[1] {
+			RESPONSE_SUCCESS => Ok(()),
+			code => Err(MailboxError::Failed(code)),
+		}
+	}
+}
+
+#[cfg(target_arch = "aarch64")]
+const CHANNEL_PROPERTY: u32 = 8;
+// The L2 uncached alias in the VideoCore's bus addresses
+#[cfg(target_arch = "aarch64")]
+const BUS_UNCACHED: u32 = 0xC000_0000;
+#[cfg(target_arch = "aarch64")]
+const MBOX_FULL: u32 = 1 << 31;
+#[cfg(target_arch = "aarch64")]
+const MBOX_EMPTY: u32 = 1 << 30;
+
+// Only one message can be in flight on the property channel.
+#[cfg(target_arch = "aarch64")]
+static MAILBOX: SpinLock<()> = SpinLock::new(());
+
+// Send a message with just this tag in it.
+#[cfg(target_arch = "aarch64")]
+pub fn request<T: Tag>(tag: T) -> Result<T::Response, MailboxError> {
+	let mut message = Message::new();
+	let tag = message.push(&tag)?;
+	message.send()?;
+	message.get(tag)
+}
+
+pub struct GetBoardRevision;
+impl Tag for GetBoardRevision {
+	const ID: u32 = 0x0001_0002;
+	const VALUE_WORDS: usize = 1;
+	type Response = u32;
+	fn response(value: &[u32]) -> u32 {
+		value[0]
+	}
+}
+
+pub struct GetBoardMac;
+impl Tag for GetBoardMac {
+	const ID: u32 = 0x0001_0003;
+	const VALUE_WORDS: usize = 2;
+	type Response = [u8; 6];
+	// The MAC is 6 bytes in network order
+	fn response(value: &[u32]) -> [u8; 6] {
+		let low = value[0].to_le_bytes();
+		let high = value[1].to_le_bytes();
+		[low[0], low[1], low[2], low[3], high[0], high[1]]
+	}
+}
+
+pub struct GetBoardSerial;
+impl Tag for GetBoardSerial {
+	const ID: u32 = 0x0001_0004;
+	const VALUE_WORDS: usize = 2;
+	type Response = u64;
+	fn response(value: &[u32]) -> u64 {
+		((value[1] as u64) << 32) | value[0] as u64
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryRange {
+	pub base: u32,
+	pub size: u32,
+}
+impl MemoryRange {
+	pub fn end(&self) -> u32 {
+		self.base + self.size
+	}
+}
+
+pub struct GetArmMemory;
+impl Tag for GetArmMemory {
+	const ID: u32 = 0x0001_0005;
+	const VALUE_WORDS: usize = 2;
+	type Response = MemoryRange;
+	fn response(value: &[u32]) -> MemoryRange {
+		MemoryRange {
+			base: value[0],
+			size: value[1],
+		}
+	}
+}
+
+pub struct GetVcMemory;
+impl Tag for GetVcMemory {
+	const ID: u32 = 0x0001_0006;
+	const VALUE_WORDS: usize = 2;
+	type Response = MemoryRange;
+	fn response(value: &[u32]) -> MemoryRange {
+		GetArmMemory::response(value)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Device {
+	SdCard = 0,
+	Uart0 = 1,
+	Uart1 = 2,
+	UsbHcd = 3,
+	I2c0 = 4,
+	I2c1 = 5,
+	I2c2 = 6,
+	Spi = 7,
+	Ccp2tx = 8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerState {
+	pub on: bool,
+	pub exists: bool,
+}
+impl PowerState {
+	fn from_bits(state: u32) -> Self {
+		// Bit 1 is set when the device doesn't exist
+		Self {
+			on: state & 1 != 0,
+			exists: state & 2 == 0,
+		}
+	}
+}
+
+pub struct GetPowerState(pub Device);
+impl Tag for GetPowerState {
+	const ID: u32 = 0x0002_0001;
+	const VALUE_WORDS: usize = 2;
+	type Response = PowerState;
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.0 as u32;
+	}
+	fn response(value: &[u32]) -> PowerState {
+		PowerState::from_bits(value[1])
+	}
+}
+
+// With wait set, the firmware waits for the device to power up before answering
+pub struct SetPowerState {
+	pub device: Device,
+	pub on: bool,
+	pub wait: bool,
+}
+impl Tag for SetPowerState {
+	const ID: u32 = 0x0002_8001;
+	const VALUE_WORDS: usize = 2;
+	type Response = PowerState;
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.device as u32;
+		value[1] = self.on as u32 | (self.wait as u32) << 1;
+	}
+	fn response(value: &[u32]) -> PowerState {
+		PowerState::from_bits(value[1])
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clock {
+	Emmc = 1,
+	Uart = 2,
+	Arm = 3,
+	Core = 4,
+	V3d = 5,
+	H264 = 6,
+	Isp = 7,
+	Sdram = 8,
+	Pixel = 9,
+	Pwm = 10,
+}
+
+// Clock rates are in Hz.  The response repeats the clock id before the rate.
+pub struct GetClockRate(pub Clock);
+impl Tag for GetClockRate {
+	const ID: u32 = 0x0003_0002;
+	const VALUE_WORDS: usize = 2;
+	type Response = u32;
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.0 as u32;
+	}
+	fn response(value: &[u32]) -> u32 {
+		value[1]
+	}
+}
+
+pub struct GetMaxClockRate(pub Clock);
+impl Tag for GetMaxClockRate {
+	const ID: u32 = 0x0003_0004;
+	const VALUE_WORDS: usize = 2;
+	type Response = u32;
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.0 as u32;
+	}
+	fn response(value: &[u32]) -> u32 {
+		value[1]
+	}
+}
+
+// Temperatures are in thousandths of a degree C.  The request and response both start with a temperature id, which is always 0.
+pub struct GetTemperature;
+impl Tag for GetTemperature {
+	const ID: u32 = 0x0003_0006;
+	const VALUE_WORDS: usize = 2;
+	type Response = u32;
+	fn response(value: &[u32]) -> u32 {
+		value[1]
+	}
+}
+
+pub struct GetMaxTemperature;
+impl Tag for GetMaxTemperature {
+	const ID: u32 = 0x0003_000A;
+	const VALUE_WORDS: usize = 2;
+	type Response = u32;
+	fn response(value: &[u32]) -> u32 {
+		value[1]
+	}
+}
+
+// The framebuffer tags.  Sizes and offsets are (x, y), and the firmware answers with what it actually set.
+pub struct AllocateBuffer {
+	pub alignment: u32,
+}
+impl Tag for AllocateBuffer {
+	const ID: u32 = 0x0004_0001;
+	const VALUE_WORDS: usize = 2;
+	// The base is a bus address
+	type Response = MemoryRange;
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.alignment;
+	}
+	fn response(value: &[u32]) -> MemoryRange {
+		GetArmMemory::response(value)
+	}
+}
+
+pub struct GetPitch;
+impl Tag for GetPitch {
+	const ID: u32 = 0x0004_0008;
+	const VALUE_WORDS: usize = 1;
+	// Bytes per row
+	type Response = u32;
+	fn response(value: &[u32]) -> u32 {
+		value[0]
+	}
+}
+
+pub struct SetPhysicalSize(pub u32, pub u32);
+impl Tag for SetPhysicalSize {
+	const ID: u32 = 0x0004_8003;
+	const VALUE_WORDS: usize = 2;
+	type Response = (u32, u32);
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.0;
+		value[1] = self.1;
+	}
+	fn response(value: &[u32]) -> (u32, u32) {
+		(value[0], value[1])
+	}
+}
+
+pub struct SetVirtualSize(pub u32, pub u32);
+impl Tag for SetVirtualSize {
+	const ID: u32 = 0x0004_8004;
+	const VALUE_WORDS: usize = 2;
+	type Response = (u32, u32);
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.0;
+		value[1] = self.1;
+	}
+	fn response(value: &[u32]) -> (u32, u32) {
+		(value[0], value[1])
+	}
+}
+
+// Bits per pixel
+pub struct SetDepth(pub u32);
+impl Tag for SetDepth {
+	const ID: u32 = 0x0004_8005;
+	const VALUE_WORDS: usize = 1;
+	type Response = u32;
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.0;
+	}
+	fn response(value: &[u32]) -> u32 {
+		value[0]
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelOrder {
+	Bgr = 0,
+	Rgb = 1,
+}
+
+pub struct SetPixelOrder(pub PixelOrder);
+impl Tag for SetPixelOrder {
+	const ID: u32 = 0x0004_8006;
+	const VALUE_WORDS: usize = 1;
+	type Response = PixelOrder;
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.0 as u32;
+	}
+	fn response(value: &[u32]) -> PixelOrder {
+		match value[0] {
+			0 => PixelOrder::Bgr,
+			_ => PixelOrder::Rgb,
+		}
+	}
+}
+
+pub struct SetVirtualOffset(pub u32, pub u32);
+impl Tag for SetVirtualOffset {
+	const ID: u32 = 0x0004_8009;
+	const VALUE_WORDS: usize = 2;
+	type Response = (u32, u32);
+	fn request(&self, value: &mut [u32]) {
+		value[0] = self.0;
+		value[1] = self.1;
+	}
+	fn response(value: &[u32]) -> (u32, u32) {
+		(value[0], value[1])
+	}
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	// Answer the tag at offset the way the firmware would
+	fn respond(message: &mut Message, offset: usize, value: &[u32]) {
+		message.words[1] = RESPONSE_SUCCESS;
+		message.words[offset + 2] = TAG_RESPONSE | (value.len() * 4) as u32;
+		message.words[offset + 3..offset + 3 + value.len()].copy_from_slice(value);
+	}
+
+	#[test]
+	fn check_cache_lines() {
+		// The words are whole cache lines, and len isn't in any of them
+		assert_eq!(core::mem::align_of::<Message>(), 64);
+		assert_eq!(core::mem::size_of::<[u32; MESSAGE_WORDS]>() % 64, 0);
+		assert_eq!(core::mem::size_of::<Message>() % 64, 0);
+	}
+
+	#[test]
+	fn check_encoding() {
+		let mut message = Message::new();
+		message.push(&GetBoardRevision).unwrap();
+		message.push(&GetClockRate(Clock::Arm)).unwrap();
+		message
+			.push(&SetPowerState {
+				device: Device::UsbHcd,
+				on: true,
+				wait: true,
+			})
+			.unwrap();
+		assert_eq!(
+			message.finish(),
+			&[
+				68,
+				REQUEST,
+				0x0001_0002,
+				4,
+				0,
+				0,
+				0x0003_0002,
+				8,
+				0,
+				3,
+				0,
+				0x0002_8001,
+				8,
+				0,
+				3,
+				0b11,
+				END_TAG
+			]
+		);
+		assert_eq!(message.words.as_ptr() as usize % 16, 0);
+	}
+
+	#[test]
+	fn check_responses() {
+		let mut message = Message::new();
+		let serial = message.push(&GetBoardSerial).unwrap();
+		let mac = message.push(&GetBoardMac).unwrap();
+		let memory = message.push(&GetArmMemory).unwrap();
+		let temp = message.push(&GetTemperature).unwrap();
+		message.finish();
+		respond(&mut message, serial.offset, &[0x89ab_cdef, 0x0123_4567]);
+		respond(&mut message, mac.offset, &[0x12eb_27b8, 0x0000_3456]);
+		respond(&mut message, memory.offset, &[0, 0x3b40_0000]);
+		// Temperature is left unanswered
+
+		assert_eq!(message.get(serial), Ok(0x0123_4567_89ab_cdef));
+		assert_eq!(message.get(mac), Ok([0xb8, 0x27, 0xeb, 0x12, 0x56, 0x34]));
+		assert_eq!(
+			message.get(memory),
+			Ok(MemoryRange {
+				base: 0,
+				size: 0x3b40_0000
+			})
+		);
+		assert_eq!(
+			message.get(temp),
+			Err(MailboxError::Unanswered(GetTemperature::ID))
+		);
+	}
+
+	#[test]
+	fn check_errors() {
+		let mut message = Message::new();
+		let revision = message.push(&GetBoardRevision).unwrap();
+		message.finish();
+		message.words[1] = 0x8000_0001;
+		assert_eq!(
+			message.get(revision),
+			Err(MailboxError::Failed(0x8000_0001))
+		);
+
+		message.words[1] = RESPONSE_SUCCESS;
+		message.words[revision.offset + 2] = TAG_RESPONSE | 8;
+		assert_eq!(
+			message.get(revision),
+			Err(MailboxError::Truncated(GetBoardRevision::ID))
+		);
+
+		let mut message = Message::new();
+		while message.push(&GetBoardSerial).is_ok() {}
+		assert_eq!(message.len, 2 + 5 * 12);
+	}
+}
@@ -1,25 +1,63 @@
 #![cfg_attr(target_arch = "aarch64", no_main, no_std)]
 #![cfg_attr(not(target_arch = "aarch64"), allow(unused))]
+// The chainloader runs instead of main, which leaves most of the kernel unused
+#![cfg_attr(feature = "chainloader", allow(dead_code))]
+#![cfg_attr(target_arch = "aarch64", feature(alloc_error_handler))]
 #![feature(asm)]
 #![feature(const_ptr_offset)]
 #![feature(naked_functions)]
 #![feature(global_asm)]
 #![allow(unused_imports)]
 
-use core::{fmt::Write, ops::Range, ptr, sync::atomic::AtomicU32};
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+use core::{
+	fmt::Write,
+	ops::Range,
+	ptr,
+	sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
 
 #[cfg(target_arch = "aarch64")]
+#[macro_use]
 mod cpu;
+#[cfg(all(target_arch = "aarch64", feature = "chainloader"))]
+mod chainloader;
+#[macro_use]
+mod console;
+mod fb_console;
+mod framebuffer;
+#[cfg(target_arch = "aarch64")]
+mod generic_timer;
 mod gpio;
 #[cfg(target_arch = "aarch64")]
 mod grit;
 #[cfg(target_arch = "aarch64")]
 mod interrupts;
 #[cfg(target_arch = "aarch64")]
+mod local_intc;
+mod logger;
+mod mailbox;
 mod memory;
 mod register;
+mod ring_buffer;
+mod shell;
+mod sync;
+mod syndrome;
+#[cfg(target_arch = "aarch64")]
+mod timer;
 mod uart;
-use self::{gpio::Gpio, uart::Uart1};
+use self::{console::Console, fb_console::FbConsole, framebuffer::Framebuffer, gpio::Gpio};
+#[cfg(target_arch = "aarch64")]
+use self::{
+	generic_timer::Timer,
+	interrupts::IrqSource,
+	timer::{AlarmChannel, Duration},
+};
+use alloc::boxed::Box;
+use log::LevelFilter;
 
 extern "C" {
 	static __int_vec_base: *const u8;
@@ -62,97 +100,159 @@ macro_rules! get_sys_reg {
 	}
 }
 
-fn main() -> ! {
-	let mut uart1 = Uart1::new();
+// The host build only exists to run the unit tests.
+#[cfg(not(target_arch = "aarch64"))]
+fn main() {}
 
-	writeln!(
-		&mut uart1,
-		"CNTPS_TVAL_EL1: {}",
-		get_sys_reg!("CNTPS_TVAL_EL1")
-	)
-	.unwrap();
+#[cfg(target_arch = "aarch64")]
+fn main() -> ! {
+	let mut serial = uart::console_uart(115200).unwrap();
+	// The IRQ doesn't get taken until setup_interrupts unmasks interrupts, and until then the writers empty the buffer themselves
+	serial.enable_interrupts();
+	console::init(serial);
+	logger::init();
 
-	writeln!(
-		&mut uart1,
+	info!(
 		"Current Exception level: {:?}",
 		cpu::ExceptionLevel::current_el()
-	)
-	.unwrap();
-	// writeln!(
-	// 	&mut uart1,
-	// 	"CNTHV_CVAL_EL2: {:b}",
-	// 	get_sys_reg!("CNTHV_CVAL_EL2")
-	// )
-	// .unwrap();
-	writeln!(&mut uart1, "CNTFRQ_EL0: {:?}", get_sys_reg!("CNTFRQ_EL0")).unwrap();
-	writeln!(&mut uart1, "CNTVCT_EL0: {:?}", get_sys_reg!("CNTVCT_EL0")).unwrap();
-	writeln!(&mut uart1, "SPSel: {:?}", get_sys_reg!("SPSel")).unwrap();
-	writeln!(&mut uart1, "DAIF: {:b}", get_sys_reg!("DAIF")).unwrap();
-	writeln!(
-		&mut uart1,
-		"RVBAR_EL3: {:p}",
-		get_sys_reg!("RVBAR_EL3") as *const u8
-	)
-	.unwrap();
-
-	interrupts::setup_interrupts(&mut uart1);
-
-	writeln!(&mut uart1, "DAIF after setup: {:b}", get_sys_reg!("DAIF")).unwrap();
-	writeln!(
-		&mut uart1,
-		"SCR_EL3 after setup: {:b}",
-		get_sys_reg!("SCR_EL3")
-	)
-	.unwrap();
-
-	let mut act_led = Gpio::new(29);
-	act_led.configure(gpio::Func::Output);
+	);
+	// debug!("CNTHV_CVAL_EL2: {:b}", get_sys_reg!("CNTHV_CVAL_EL2"));
+	debug!("CNTFRQ_EL0: {:?}", get_sys_reg!("CNTFRQ_EL0"));
+	debug!("CNTVCT_EL0: {:?}", get_sys_reg!("CNTVCT_EL0"));
+	debug!("SPSel: {:?}", get_sys_reg!("SPSel"));
+	debug!("DAIF: {:b}", get_sys_reg!("DAIF"));
+
+	interrupts::setup_interrupts();
+
+	debug!("DAIF after setup: {:b}", get_sys_reg!("DAIF"));
+	info!("Heap: {:?}", memory::heap::stats());
+	info!("Free frames: {}", memory::frames::free_frames());
+	info!(
+		"Board revision: {:x?}, ARM clock: {:?}Hz, Temperature: {:?}",
+		mailbox::request(mailbox::GetBoardRevision),
+		mailbox::request(mailbox::GetClockRate(mailbox::Clock::Arm)),
+		mailbox::request(mailbox::GetTemperature)
+	);
+	match Framebuffer::allocate(1024, 768) {
+		Ok(fb) => {
+			let mut console = FbConsole::new(fb);
+			writeln!(&mut console, "\x1b[1;32mbaremetal-pi\x1b[0m is up.").unwrap();
+			// Only the important stuff goes on screen
+			logger::add_sink(Box::new(console), LevelFilter::Info);
+		}
+		Err(e) => warn!("No framebuffer: {:?}", e),
+	}
+
+	for core in 1..cpu::CORE_COUNT {
+		unsafe { cpu::start_core(core, move || worker(core), cpu::core_stack(core)) };
+		info!("Core {} is online", core);
+	}
+
+	let mut act_led = Gpio::take(29)
+		.expect("Someone else has the ACT LED")
+		.into_output();
 
 	for _ in 0..1 {
-		writeln!(&mut uart1, "Hello World!").unwrap();
-		act_led.high();
-		delay(1_000_000);
+		println!("Hello World!");
+		act_led.set_high();
+		timer::sleep(Duration::from_millis(200));
 
-		act_led.low();
-		delay(4_000_000);
+		act_led.set_low();
+		timer::sleep(Duration::from_millis(800));
 	}
 
 	unsafe {
 		// asm!("wfi");
-		asm!("smc {}", const 42);
+		asm!("svc {}", const 42);
 	}
 
-	// let timeout = 1000;
-	// let el3_timer_ctl = 0b001; // Enable the timer and unmask the interrupts
-	// unsafe {
-	// 	asm!(
-	// 		"msr cntps_tval_el1, {:x}",
-	// 		"msr cntps_ctl_el1, {:x}",
-	// 		// "wfi",
-	// 		in(reg) timeout,
-	// 		in(reg) el3_timer_ctl
-	// 	);
-	// // }
-	// loop {
-	// 	let tval = get_sys_reg!("CNTPS_TVAL_EL1");
-	// 	writeln!(&mut uart1, "CNTPS_TVAL_EL1: {}", tval).unwrap();
-
-	// 	delay(1_000_000);
-	// }
+	shell::register(&cpu::COMMANDS);
+	shell::register(&gpio::COMMANDS);
+	shell::register(&interrupts::COMMANDS);
+	shell::register(&logger::COMMANDS);
+	shell::register(&memory::COMMANDS);
+	shell::register(&timer::COMMANDS);
+	shell::register(&COMMANDS);
+	shell::run(&mut Console);
+
+	// panic!("End of program.");
+}
+
+#[cfg(target_arch = "aarch64")]
+const TICK_HZ: u64 = 100;
+#[cfg(target_arch = "aarch64")]
+static CORE_TICKS: [AtomicUsize; cpu::CORE_COUNT] = [
+	AtomicUsize::new(0),
+	AtomicUsize::new(0),
+	AtomicUsize::new(0),
+	AtomicUsize::new(0),
+];
+#[cfg(target_arch = "aarch64")]
+static CORE_PINGS: [AtomicUsize; cpu::CORE_COUNT] = [
+	AtomicUsize::new(0),
+	AtomicUsize::new(0),
+	AtomicUsize::new(0),
+	AtomicUsize::new(0),
+];
+
+// Each secondary core runs its own timer tick off of its physical generic timer.
+#[cfg(target_arch = "aarch64")]
+fn worker(_core: usize) -> ! {
+	interrupts::setup_core_interrupts();
+	interrupts::register_handler(IrqSource::CoreTimer(Timer::Physical), tick);
+	interrupts::register_handler(IrqSource::Mailbox(0), ping);
+	Timer::Physical.set_tval((generic_timer::frequency() / TICK_HZ) as u32);
+	Timer::Physical.enable();
 	loop {
 		unsafe {
-			let lower = core::ptr::read_volatile(memory::timer::TIMER_COUNTER_LO);
-			let compare = lower + 3 * 250 * 1000;
-			core::ptr::write_volatile(memory::timer::TIMER_COMPARE_1, compare);
-			writeln!(&mut uart1, "Setting Timer compare 1 to: {}", compare).unwrap();
-
 			asm!("wfi");
 		}
 	}
+}
 
-	// panic!("End of program.");
+#[cfg(target_arch = "aarch64")]
+fn tick() {
+	// Writing TVAL re-arms the timer and clears its interrupt
+	Timer::Physical.set_tval((generic_timer::frequency() / TICK_HZ) as u32);
+	CORE_TICKS[cpu::core_id()].fetch_add(1, Ordering::Relaxed);
 }
 
+#[cfg(target_arch = "aarch64")]
+fn ping() {
+	local_intc::take(0);
+	CORE_PINGS[cpu::core_id()].fetch_add(1, Ordering::Relaxed);
+}
+
+// Ping every secondary core, then show how many ticks and pings each one has seen.
+#[cfg(target_arch = "aarch64")]
+fn cores(args: &[&str], out: &mut dyn Write) -> Result<(), shell::CommandError> {
+	if !args.is_empty() {
+		return Err(shell::CommandError::Usage);
+	}
+	for core in 1..cpu::CORE_COUNT {
+		local_intc::send(core, 0, 1);
+	}
+	timer::sleep(Duration::from_millis(1));
+	for core in 0..cpu::CORE_COUNT {
+		writeln!(
+			out,
+			"Core {}: {} ticks, {} pings",
+			core,
+			CORE_TICKS[core].load(Ordering::Relaxed),
+			CORE_PINGS[core].load(Ordering::Relaxed)
+		)?;
+	}
+	Ok(())
+}
+
+#[cfg(target_arch = "aarch64")]
+static COMMANDS: [shell::Command; 1] = [shell::Command {
+	name: "cores",
+	args: "",
+	help: "Ping the secondary cores and show their tick counts",
+	run: cores,
+}];
+
 #[cfg(all(not(target_arch = "aarch64"), test))]
 mod tests {
 	use super::*;
@@ -2,12 +2,110 @@
 
 use core::sync::atomic::AtomicU32;
 
+pub mod frames;
+pub mod heap;
+#[cfg(target_arch = "aarch64")]
+pub mod paging;
+
 // Peripheral Base address in bus coords: 0x7e000000
 pub const IO_BASE: u64 = 0x3F000000;
+// The ARM local peripherals (core timers, mailboxes, and local interrupt routing)
+pub const LOCAL_BASE: u64 = 0x4000_0000;
+pub const LOCAL_SIZE: u64 = 0x4_0000;
+
+// If the firmware won't tell us, assume the default split (gpu_mem=64) on a 1GB Pi 3.
+pub const DEFAULT_ARM_MEMORY_TOP: usize = 0x3C00_0000;
+// The heap gets this fraction of the ARM memory after the kernel, and the frame allocator gets whatever is left.
+pub const HEAP_FRACTION: usize = 4;
+// But never less than this, so that the chainloader's receive buffer (from the frame allocator) can't land where the kernel image goes.
+pub const MIN_HEAP_SIZE: usize = 16 << 20;
+
+// Where the heap goes: right after the kernel, frame aligned at the end so that the frame allocator doesn't lose a partial frame.
+pub fn heap_region(kernel_end: usize, arm_end: usize) -> core::ops::Range<usize> {
+	let size = (arm_end.saturating_sub(kernel_end) / HEAP_FRACTION).max(MIN_HEAP_SIZE);
+	let end = (kernel_end + size) & !(frames::FRAME_SIZE - 1);
+	kernel_end..end
+}
+
+// Set up the heap and frame allocator.  This has to run after the MMU is on, because their locks need exclusive loads / stores.
+#[cfg(target_arch = "aarch64")]
+pub fn init() {
+	use crate::{
+		cpu,
+		mailbox::{self, GetArmMemory, GetVcMemory},
+	};
+	extern "C" {
+		static __stack_start: u8;
+		static __kernel_end: u8;
+	}
+	// The kernel image starts where the boot stack ends (see link.ld)
+	let kernel_start = unsafe { core::ptr::addr_of!(__stack_start) } as usize;
+	let kernel_end = unsafe { core::ptr::addr_of!(__kernel_end) } as usize;
+	let arm = mailbox::request(GetArmMemory)
+		.map(|range| range.base as usize..range.end() as usize)
+		.unwrap_or(0..DEFAULT_ARM_MEMORY_TOP);
+	let videocore = mailbox::request(GetVcMemory)
+		.map(|range| range.base as usize..range.end() as usize)
+		.unwrap_or(arm.end..IO_BASE as usize);
+	let heap = heap_region(kernel_end, arm.end);
+	heap::init(heap.clone());
+	frames::init(&frames::MemoryMap {
+		arm,
+		videocore,
+		armstub: 0..0x1000,
+		stacks: kernel_start - cpu::CORE_COUNT * cpu::CORE_STACK_SIZE..kernel_start,
+		kernel: kernel_start..kernel_end,
+		heap,
+	});
+}
+
+#[cfg(target_arch = "aarch64")]
+fn mem(args: &[&str], out: &mut dyn core::fmt::Write) -> Result<(), crate::shell::CommandError> {
+	if !args.is_empty() {
+		return Err(crate::shell::CommandError::Usage);
+	}
+	let heap = heap::stats();
+	writeln!(
+		out,
+		"Heap: {} / {} bytes used (peak {}), {} free blocks, largest {}, {}% fragmented",
+		heap.used,
+		heap.size,
+		heap.peak,
+		heap.free_blocks,
+		heap.largest_free,
+		heap.fragmentation()
+	)?;
+	let (free, total) = (frames::free_frames(), frames::total_frames());
+	writeln!(
+		out,
+		"Frames: {} / {} free ({} / {} MiB)",
+		free,
+		total,
+		(free * frames::FRAME_SIZE) >> 20,
+		(total * frames::FRAME_SIZE) >> 20
+	)?;
+	Ok(())
+}
+
+#[cfg(target_arch = "aarch64")]
+pub static COMMANDS: [crate::shell::Command; 1] = [crate::shell::Command {
+	name: "mem",
+	args: "",
+	help: "Heap and frame allocator usage",
+	run: mem,
+}];
 
 pub mod gpio {
 	use super::*;
 	pub const GPIO_BASE: *const AtomicU32 = (IO_BASE + 0x20_0000) as *const AtomicU32;
+	// Event detect registers.  Each is a pair: pins 0-31, then 32-53.
+	pub const GPEDS0: *mut u32 = (IO_BASE + 0x20_0040) as *mut u32;
+	pub const GPREN0: *mut u32 = (IO_BASE + 0x20_004C) as *mut u32;
+	pub const GPFEN0: *mut u32 = (IO_BASE + 0x20_0058) as *mut u32;
+	pub const GPHEN0: *mut u32 = (IO_BASE + 0x20_0064) as *mut u32;
+	pub const GPLEN0: *mut u32 = (IO_BASE + 0x20_0070) as *mut u32;
+	pub const GPAREN0: *mut u32 = (IO_BASE + 0x20_007C) as *mut u32;
+	pub const GPAFEN0: *mut u32 = (IO_BASE + 0x20_0088) as *mut u32;
 }
 
 // The base (bus) address for the interrupt registers is: 0x7E00B000
@@ -27,6 +125,29 @@ pub mod interrupts {
 	pub const IRQ_DISABLE_BASIC: *mut u32 = (INTERRUPT_BASE + 0x224) as *mut u32;
 }
 
+pub mod mailbox {
+	use super::*;
+	pub const MBOX_BASE: u64 = IO_BASE + 0xB880;
+	pub const MBOX_READ: *const u32 = MBOX_BASE as *const u32;
+	pub const MBOX_STATUS: *const u32 = (MBOX_BASE + 0x18) as *const u32;
+	pub const MBOX_WRITE: *mut u32 = (MBOX_BASE + 0x20) as *mut u32;
+}
+
+// The ARM local peripherals.  The per-core registers are arrays indexed by core number.
+pub mod local {
+	use super::*;
+	pub const LOCAL_CONTROL: *mut u32 = LOCAL_BASE as *mut u32;
+	pub const CORE_TIMER_PRESCALER: *mut u32 = (LOCAL_BASE + 0x08) as *mut u32;
+	pub const GPU_INT_ROUTING: *mut u32 = (LOCAL_BASE + 0x0C) as *mut u32;
+	pub const CORE_TIMER_INT_CTL: *mut u32 = (LOCAL_BASE + 0x40) as *mut u32;
+	pub const CORE_MAILBOX_INT_CTL: *mut u32 = (LOCAL_BASE + 0x50) as *mut u32;
+	pub const CORE_IRQ_SOURCE: *const u32 = (LOCAL_BASE + 0x60) as *const u32;
+	pub const CORE_FIQ_SOURCE: *const u32 = (LOCAL_BASE + 0x70) as *const u32;
+	// 4 mailboxes per core: index with core * 4 + mailbox.  Writing to SET sets bits, writing to CLEAR clears them, and reading CLEAR gives the current value.
+	pub const CORE_MAILBOX_SET: *mut u32 = (LOCAL_BASE + 0x80) as *mut u32;
+	pub const CORE_MAILBOX_CLEAR: *mut u32 = (LOCAL_BASE + 0xC0) as *mut u32;
+}
+
 pub mod timer {
 	use super::*;
 	pub const TIMER_BASE: u64 = IO_BASE + 0x3000;
@@ -42,8 +163,45 @@ pub mod timer {
 pub mod uart {
 	use super::*;
 	pub const AUX_MU_IO_REG: *mut u32 = (IO_BASE + 0x21_5040) as *mut u32;
+	pub const AUX_MU_IER_REG: *mut u32 = (IO_BASE + 0x21_5044) as *mut u32;
+	pub const AUX_MU_IIR_REG: *mut u32 = (IO_BASE + 0x21_5048) as *mut u32;
 	pub const AUX_MU_LCR_REG: *mut u32 = (IO_BASE + 0x21_504c) as *mut u32;
 	pub const AUX_MU_CNTL_REG: *mut u32 = (IO_BASE + 0x21_5060) as *mut u32;
 	pub const AUX_MU_STAT_REG: *mut u32 = (IO_BASE + 0x21_5064) as *mut u32;
 	pub const AUX_MU_BAUD: *mut u32 = (IO_BASE + 0x21_5068) as *mut u32;
+
+	// The PL011 (UART0)
+	pub const UART0_BASE: u64 = IO_BASE + 0x20_1000;
+	pub const UART0_DR: *mut u32 = UART0_BASE as *mut u32;
+	pub const UART0_FR: *const u32 = (UART0_BASE + 0x18) as *const u32;
+	pub const UART0_IBRD: *mut u32 = (UART0_BASE + 0x24) as *mut u32;
+	pub const UART0_FBRD: *mut u32 = (UART0_BASE + 0x28) as *mut u32;
+	pub const UART0_LCRH: *mut u32 = (UART0_BASE + 0x2C) as *mut u32;
+	pub const UART0_CR: *mut u32 = (UART0_BASE + 0x30) as *mut u32;
+	pub const UART0_IMSC: *mut u32 = (UART0_BASE + 0x38) as *mut u32;
+	pub const UART0_MIS: *const u32 = (UART0_BASE + 0x40) as *const u32;
+	pub const UART0_ICR: *mut u32 = (UART0_BASE + 0x44) as *mut u32;
+}
+
+// The power management block.  Its registers only take writes that carry the password in the top byte.
+pub mod pm {
+	use super::*;
+	pub const PM_BASE: u64 = IO_BASE + 0x10_0000;
+	pub const PM_RSTC: *mut u32 = (PM_BASE + 0x1C) as *mut u32;
+	pub const PM_WDOG: *mut u32 = (PM_BASE + 0x24) as *mut u32;
+	pub const PM_PASSWORD: u32 = 0x5a00_0000;
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_heap_region() {
+		// A quarter of what's after the kernel, ending on a frame boundary
+		assert_eq!(heap_region(0x10_0123, 0x3C00_0000), 0x10_0123..0xF0C_0000);
+		// Small Pis still get the minimum
+		assert_eq!(heap_region(0x10_0000, 0x200_0000), 0x10_0000..0x110_0000);
+		assert_eq!(heap_region(0x10_0000, 0), 0x10_0000..0x110_0000);
+	}
 }
@@ -0,0 +1,206 @@
+use bitvec::{order::Lsb0, view::BitView};
+use core::ops::Range;
+
+#[cfg(target_arch = "aarch64")]
+use crate::sync::SpinLock;
+
+pub const FRAME_SIZE: usize = 4096;
+
+// What the frame allocator needs to know about physical memory.  Frames outside of arm, or touching any of the other ranges, are never handed out.
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+	// The memory that the firmware gives to the ARM.  The VideoCore gets the memory above it.
+	pub arm: Range<usize>,
+	pub videocore: Range<usize>,
+	// The armstub and its spin table
+	pub armstub: Range<usize>,
+	pub stacks: Range<usize>,
+	pub kernel: Range<usize>,
+	pub heap: Range<usize>,
+}
+impl MemoryMap {
+	fn reserved(&self) -> [&Range<usize>; 5] {
+		[
+			&self.videocore,
+			&self.armstub,
+			&self.stacks,
+			&self.kernel,
+			&self.heap,
+		]
+	}
+}
+
+fn align_up(n: usize, align: usize) -> usize {
+	(n + align - 1) / align * align
+}
+
+// One bit per frame (set means in use), starting from physical address 0 so that a frame's number is just its address / FRAME_SIZE.
+pub struct FrameAllocator<const WORDS: usize> {
+	bits: [usize; WORDS],
+	frames: usize,
+	free: usize,
+}
+
+impl<const WORDS: usize> FrameAllocator<WORDS> {
+	pub const CAPACITY: usize = WORDS * usize::BITS as usize;
+
+	pub const fn empty() -> Self {
+		Self {
+			bits: [0; WORDS],
+			frames: 0,
+			free: 0,
+		}
+	}
+
+	pub fn init(&mut self, map: &MemoryMap) {
+		self.frames = (map.arm.end / FRAME_SIZE).min(Self::CAPACITY);
+		let bits = self.bits.view_bits_mut::<Lsb0>();
+		bits.set_all(true);
+		// Only whole frames inside the ARM's memory are usable, but any frame that a reserved range touches is off limits.
+		let usable = align_up(map.arm.start, FRAME_SIZE) / FRAME_SIZE..self.frames;
+		bits[usable].set_all(false);
+		for range in map.reserved().iter() {
+			let start = (range.start / FRAME_SIZE).min(self.frames);
+			let end = (align_up(range.end, FRAME_SIZE) / FRAME_SIZE).min(self.frames);
+			if start < end {
+				bits[start..end].set_all(true);
+			}
+		}
+		self.free = bits[..self.frames].count_zeros();
+	}
+
+	// Find count contiguous frames whose physical address is a multiple of align (a power of two, anything under FRAME_SIZE is the same as FRAME_SIZE).  Returns the physical address of the first frame.
+	pub fn allocate(&mut self, count: usize, align: usize) -> Option<usize> {
+		assert!(count > 0 && align.is_power_of_two());
+		let step = (align / FRAME_SIZE).max(1);
+		let bits = self.bits.view_bits_mut::<Lsb0>();
+		let mut start = 0;
+		while start + count <= self.frames {
+			match bits[start..start + count].last_one() {
+				// Any run that includes that frame won't work, so skip past it
+				Some(used) => start = align_up(start + used + 1, step),
+				None => {
+					bits[start..start + count].set_all(true);
+					self.free -= count;
+					return Some(start * FRAME_SIZE);
+				}
+			}
+		}
+		None
+	}
+
+	pub fn free(&mut self, addr: usize, count: usize) {
+		assert!(addr % FRAME_SIZE == 0);
+		let start = addr / FRAME_SIZE;
+		assert!(start + count <= self.frames);
+		let bits = &mut self.bits.view_bits_mut::<Lsb0>()[start..start + count];
+		assert!(
+			bits.all(),
+			"Freed frames that weren't allocated: {:#x}",
+			addr
+		);
+		bits.set_all(false);
+		self.free += count;
+	}
+
+	pub fn free_frames(&self) -> usize {
+		self.free
+	}
+	pub fn total_frames(&self) -> usize {
+		self.frames
+	}
+}
+
+// Enough frames to cover 1GB
+#[cfg(target_arch = "aarch64")]
+static FRAMES: SpinLock<FrameAllocator<{ 0x4000_0000 / FRAME_SIZE / usize::BITS as usize }>> =
+	SpinLock::new(FrameAllocator::empty());
+
+#[cfg(target_arch = "aarch64")]
+pub fn init(map: &MemoryMap) {
+	FRAMES.lock().init(map);
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn allocate(count: usize, align: usize) -> Option<usize> {
+	FRAMES.lock().allocate(count, align)
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn free(addr: usize, count: usize) {
+	FRAMES.lock().free(addr, count);
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn free_frames() -> usize {
+	FRAMES.lock().free_frames()
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn total_frames() -> usize {
+	FRAMES.lock().total_frames()
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	// 1MB of "memory" that looks like the Pi's layout, shrunk down
+	fn map() -> MemoryMap {
+		MemoryMap {
+			arm: 0..0xC_0000,
+			videocore: 0xC_0000..0x10_0000,
+			armstub: 0..0x1000,
+			stacks: 0x4_0000..0x8_0000,
+			kernel: 0x8_0000..0x8_2345,
+			heap: 0x8_2345..0xA_0000,
+		}
+	}
+
+	fn allocator() -> FrameAllocator<4> {
+		let mut frames = FrameAllocator::empty();
+		frames.init(&map());
+		frames
+	}
+
+	#[test]
+	fn check_reservations() {
+		let frames = allocator();
+		assert_eq!(frames.total_frames(), 0xC0);
+		// 0x1000..0x40000 and 0xA0000..0xC0000
+		assert_eq!(frames.free_frames(), 0x3F + 0x20);
+	}
+
+	#[test]
+	fn check_allocate() {
+		let mut frames = allocator();
+		assert_eq!(frames.allocate(1, FRAME_SIZE), Some(0x1000));
+		assert_eq!(frames.allocate(2, FRAME_SIZE), Some(0x2000));
+		// Aligned runs skip over the frames that are in the way
+		assert_eq!(frames.allocate(4, 0x4000), Some(0x4000));
+		assert_eq!(frames.allocate(0x20, 0x2_0000), Some(0x2_0000));
+		// Nothing below the stacks is big enough anymore
+		assert_eq!(frames.allocate(0x20, FRAME_SIZE), Some(0xA_0000));
+		assert_eq!(frames.allocate(0x20, FRAME_SIZE), None);
+		assert_eq!(frames.free_frames(), 0x3F - 0x27);
+	}
+
+	#[test]
+	fn check_free() {
+		let mut frames = allocator();
+		let before = frames.free_frames();
+		let run = frames.allocate(8, FRAME_SIZE).unwrap();
+		frames.free(run, 8);
+		assert_eq!(frames.free_frames(), before);
+		assert_eq!(frames.allocate(8, FRAME_SIZE), Some(run));
+	}
+
+	#[test]
+	#[should_panic]
+	fn check_double_free() {
+		let mut frames = allocator();
+		let run = frames.allocate(1, FRAME_SIZE).unwrap();
+		frames.free(run, 1);
+		frames.free(run, 1);
+	}
+}
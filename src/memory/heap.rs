@@ -0,0 +1,296 @@
+use crate::sync::SpinLock;
+use core::{
+	alloc::{GlobalAlloc, Layout},
+	mem,
+	ops::Range,
+	ptr::null_mut,
+};
+
+// Free blocks hold their own size and a pointer to the next free block.
+struct Hole {
+	size: usize,
+	next: *mut Hole,
+}
+
+const MIN_BLOCK: usize = mem::size_of::<Hole>();
+const BLOCK_ALIGN: usize = mem::align_of::<Hole>();
+
+fn align_up(addr: usize, align: usize) -> usize {
+	(addr + align - 1) & !(align - 1)
+}
+
+// Every block has to be able to hold a Hole once it's freed, and keep the blocks after it aligned for one.
+fn block_size(layout: &Layout) -> usize {
+	align_up(layout.size().max(MIN_BLOCK), BLOCK_ALIGN)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeapStats {
+	pub size: usize,
+	pub used: usize,
+	pub peak: usize,
+	pub free_blocks: usize,
+	pub largest_free: usize,
+}
+impl HeapStats {
+	pub fn free(&self) -> usize {
+		self.size - self.used
+	}
+	// The percent of free memory that can't be handed out as one allocation.  0 means it's all in one block.
+	pub fn fragmentation(&self) -> usize {
+		match self.free() {
+			0 => 0,
+			free => 100 - self.largest_free * 100 / free,
+		}
+	}
+}
+
+// First fit over an address ordered list of free blocks.  Freed blocks get merged with their neighbours.
+pub struct LinkedListHeap {
+	head: *mut Hole,
+	size: usize,
+	used: usize,
+	peak: usize,
+}
+unsafe impl Send for LinkedListHeap {}
+
+impl LinkedListHeap {
+	pub const fn empty() -> Self {
+		Self {
+			head: null_mut(),
+			size: 0,
+			used: 0,
+			peak: 0,
+		}
+	}
+	// SAFETY: [start, start + size) has to be memory that nothing else uses, for as long as the heap is around.
+	pub unsafe fn init(&mut self, start: usize, size: usize) {
+		let aligned = align_up(start, BLOCK_ALIGN);
+		let size = size.saturating_sub(aligned - start) & !(BLOCK_ALIGN - 1);
+		*self = Self::empty();
+		if size >= MIN_BLOCK {
+			self.size = size;
+			self.insert(aligned, size);
+		}
+	}
+
+	pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+		let size = block_size(&layout);
+		let align = layout.align().max(BLOCK_ALIGN);
+		let mut prev: *mut *mut Hole = &mut self.head;
+		unsafe {
+			while !(*prev).is_null() {
+				let hole = *prev;
+				let hole_start = hole as usize;
+				let hole_end = hole_start + (*hole).size;
+
+				// Whatever is left in front of or behind the allocation has to be big enough to stay a hole.
+				let mut start = align_up(hole_start, align);
+				if start != hole_start && start - hole_start < MIN_BLOCK {
+					start = align_up(hole_start + MIN_BLOCK, align);
+				}
+				let fits = match start.checked_add(size) {
+					Some(end) => end == hole_end || (end < hole_end && hole_end - end >= MIN_BLOCK),
+					None => false,
+				};
+				if fits {
+					let end = start + size;
+					let mut link = (*hole).next;
+					if end != hole_end {
+						let back = end as *mut Hole;
+						back.write(Hole {
+							size: hole_end - end,
+							next: link,
+						});
+						link = back;
+					}
+					if start != hole_start {
+						hole.write(Hole {
+							size: start - hole_start,
+							next: link,
+						});
+						link = hole;
+					}
+					*prev = link;
+
+					self.used += size;
+					self.peak = self.peak.max(self.used);
+					return start as *mut u8;
+				}
+				prev = &mut (*hole).next;
+			}
+		}
+		null_mut()
+	}
+
+	// SAFETY: ptr has to have come from allocate with the same layout.
+	pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+		let size = block_size(&layout);
+		self.used -= size;
+		self.insert(ptr as usize, size);
+	}
+
+	unsafe fn insert(&mut self, addr: usize, size: usize) {
+		let mut prev: *mut Hole = null_mut();
+		let mut next = self.head;
+		while !next.is_null() && (next as usize) < addr {
+			prev = next;
+			next = (*next).next;
+		}
+
+		let hole = addr as *mut Hole;
+		hole.write(Hole { size, next });
+		if !next.is_null() && addr + size == next as usize {
+			(*hole).size += (*next).size;
+			(*hole).next = (*next).next;
+		}
+		if prev.is_null() {
+			self.head = hole;
+		} else if prev as usize + (*prev).size == addr {
+			(*prev).size += (*hole).size;
+			(*prev).next = (*hole).next;
+		} else {
+			(*prev).next = hole;
+		}
+	}
+
+	pub fn stats(&self) -> HeapStats {
+		let mut stats = HeapStats {
+			size: self.size,
+			used: self.used,
+			peak: self.peak,
+			..Default::default()
+		};
+		let mut hole = self.head;
+		while !hole.is_null() {
+			unsafe {
+				stats.free_blocks += 1;
+				stats.largest_free = stats.largest_free.max((*hole).size);
+				hole = (*hole).next;
+			}
+		}
+		stats
+	}
+}
+
+pub struct Heap(SpinLock<LinkedListHeap>);
+impl Heap {
+	pub const fn empty() -> Self {
+		Self(SpinLock::new(LinkedListHeap::empty()))
+	}
+	// SAFETY: Same as LinkedListHeap::init
+	pub unsafe fn init(&self, start: usize, size: usize) {
+		self.0.lock().init(start, size);
+	}
+	pub fn stats(&self) -> HeapStats {
+		self.0.lock().stats()
+	}
+}
+unsafe impl GlobalAlloc for Heap {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		self.0.lock().allocate(layout)
+	}
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		self.0.lock().deallocate(ptr, layout);
+	}
+}
+
+#[cfg(target_arch = "aarch64")]
+#[global_allocator]
+static HEAP: Heap = Heap::empty();
+
+// This has to run after the MMU is on, because the lock needs exclusive loads / stores.
+#[cfg(target_arch = "aarch64")]
+pub fn init(region: Range<usize>) {
+	unsafe { HEAP.init(region.start, region.end - region.start) };
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn stats() -> HeapStats {
+	HEAP.stats()
+}
+
+#[cfg(target_arch = "aarch64")]
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+	panic!(
+		"Failed to allocate {} bytes (align {}): {:?}",
+		layout.size(),
+		layout.align(),
+		stats()
+	);
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	#[repr(C, align(4096))]
+	struct Arena([u8; 4096]);
+
+	fn heap(arena: &mut Arena) -> LinkedListHeap {
+		let mut heap = LinkedListHeap::empty();
+		unsafe { heap.init(arena.0.as_mut_ptr() as usize, arena.0.len()) };
+		heap
+	}
+
+	#[test]
+	fn check_alloc_and_free() {
+		let mut arena = Arena([0; 4096]);
+		let mut heap = heap(&mut arena);
+		let layout = Layout::from_size_align(100, 8).unwrap();
+		let a = heap.allocate(layout);
+		let b = heap.allocate(layout);
+		assert!(!a.is_null() && !b.is_null());
+		assert_eq!(b as usize - a as usize, 104);
+		assert_eq!(heap.stats().used, 208);
+
+		unsafe { heap.deallocate(a, layout) };
+		// The freed block is reused first
+		assert_eq!(heap.allocate(layout), a);
+		unsafe {
+			heap.deallocate(a, layout);
+			heap.deallocate(b, layout);
+		}
+		let stats = heap.stats();
+		assert_eq!(stats.used, 0);
+		assert_eq!(stats.peak, 208);
+		assert_eq!(stats.free_blocks, 1);
+		assert_eq!(stats.largest_free, 4096);
+	}
+
+	#[test]
+	fn check_alignment() {
+		let mut arena = Arena([0; 4096]);
+		let mut heap = heap(&mut arena);
+		let small = heap.allocate(Layout::from_size_align(8, 8).unwrap());
+		let page = heap.allocate(Layout::from_size_align(64, 1024).unwrap());
+		assert_eq!(page as usize % 1024, 0);
+		assert!(page as usize > small as usize);
+		// The padding in front of the aligned block is still usable
+		let filler = heap.allocate(Layout::from_size_align(256, 8).unwrap());
+		assert!((filler as usize) < page as usize);
+	}
+
+	#[test]
+	fn check_fragmentation() {
+		let mut arena = Arena([0; 4096]);
+		let mut heap = heap(&mut arena);
+		let layout = Layout::from_size_align(1024, 8).unwrap();
+		let blocks: Vec<_> = (0..4).map(|_| heap.allocate(layout)).collect();
+		assert!(heap.allocate(layout).is_null());
+		unsafe {
+			heap.deallocate(blocks[0], layout);
+			heap.deallocate(blocks[2], layout);
+		}
+		let stats = heap.stats();
+		assert_eq!(stats.free_blocks, 2);
+		assert_eq!(stats.fragmentation(), 50);
+		// Freeing the block between the holes merges all three
+		unsafe { heap.deallocate(blocks[1], layout) };
+		let stats = heap.stats();
+		assert_eq!(stats.free_blocks, 1);
+		assert_eq!(stats.largest_free, 3072);
+		assert_eq!(stats.fragmentation(), 0);
+	}
+}
@@ -0,0 +1,113 @@
+// Identity mapped translation tables for the whole 4GB physical address space we care about.  We use a 4KB granule, and because T0SZ is 32, translation starts at level 1: each L1 entry covers 1GB and each L2 entry is a 2MB block.
+use super::{IO_BASE, LOCAL_BASE};
+use crate::cpu::{ExceptionLevel, KERNEL_EL};
+
+const GRANULE: usize = 4096;
+const ENTRIES: usize = GRANULE / 8;
+const L1_BLOCK: u64 = 1 << 30;
+const L2_BLOCK: u64 = 1 << 21;
+
+// MAIR attribute indices
+const ATTR_DEVICE: u64 = 0; // Device-nGnRE
+const ATTR_NORMAL: u64 = 1; // Normal, Inner + Outer Write-Back Read/Write-Allocate
+const MAIR: u64 = (0x04 << (8 * ATTR_DEVICE)) | (0xFF << (8 * ATTR_NORMAL));
+
+// Descriptor bits
+const VALID: u64 = 1 << 0;
+const TABLE: u64 = 1 << 1; // In an L1/L2 entry: 1 is a table, 0 is a block
+const ACCESS_FLAG: u64 = 1 << 10;
+const INNER_SHAREABLE: u64 = 0b11 << 8;
+// XN for the EL2 regime.  In the EL1&0 regime bit 54 is UXN and bit 53 is PXN.
+const EXECUTE_NEVER: u64 = match KERNEL_EL {
+	ExceptionLevel::EL1 => (1 << 54) | (1 << 53),
+	_ => 1 << 54,
+};
+const fn attr_index(i: u64) -> u64 {
+	i << 2
+}
+
+const NORMAL_BLOCK: u64 = VALID | ACCESS_FLAG | INNER_SHAREABLE | attr_index(ATTR_NORMAL);
+const DEVICE_BLOCK: u64 = VALID | ACCESS_FLAG | EXECUTE_NEVER | attr_index(ATTR_DEVICE);
+
+// TCR: T0SZ = 32 (4GB), walks are Inner Shareable Write-Back cacheable, 4KB granule, 32bit physical addresses.
+const TCR_TTBR0: u64 = 32 | (0b01 << 8) | (0b01 << 10) | (0b11 << 12);
+const TCR: u64 = match KERNEL_EL {
+	// EPD1: There's nothing in TTBR1, so don't walk it.  IPS (bits 32-34) is 0 for 32bit physical addresses.
+	ExceptionLevel::EL1 => TCR_TTBR0 | (1 << 23),
+	// Bits 31 and 23 are RES1.  PS (bits 16-18) is 0 for 32bit physical addresses.
+	_ => TCR_TTBR0 | (1 << 23) | (1 << 31),
+};
+
+// Invalidate all the TLB entries for KERNEL_EL's translation regime
+#[cfg(feature = "el2")]
+macro_rules! tlbi_all {
+	() => {
+		"tlbi alle2"
+	};
+}
+#[cfg(not(feature = "el2"))]
+macro_rules! tlbi_all {
+	() => {
+		"tlbi vmalle1"
+	};
+}
+
+// SCTLR bits
+const SCTLR_M: u64 = 1 << 0;
+const SCTLR_A: u64 = 1 << 1;
+const SCTLR_C: u64 = 1 << 2;
+const SCTLR_I: u64 = 1 << 12;
+
+#[repr(C, align(4096))]
+struct Table([u64; ENTRIES]);
+
+static mut L1: Table = Table([0; ENTRIES]);
+// Second level table for the first GB, which holds RAM and the legacy peripherals.
+static mut L2: Table = Table([0; ENTRIES]);
+
+// Build the tables and turn on the MMU + caches for the boot core.  This has to run before anything expects atomics to work.
+pub fn init() {
+	unsafe {
+		for (i, entry) in L2.0.iter_mut().enumerate() {
+			let addr = i as u64 * L2_BLOCK;
+			let attributes = if addr < IO_BASE {
+				NORMAL_BLOCK
+			} else {
+				DEVICE_BLOCK
+			};
+			*entry = addr | attributes;
+		}
+		L1.0[0] = core::ptr::addr_of!(L2) as u64 | VALID | TABLE;
+		// The ARM local peripherals (and nothing else we use) live in the second GB.
+		L1.0[(LOCAL_BASE / L1_BLOCK) as usize] = LOCAL_BASE | DEVICE_BLOCK;
+	}
+	enable();
+}
+
+// Point this core at the tables and turn on the MMU.  Secondary cores call this directly because the boot core has already built the tables.
+pub fn enable() {
+	let ttbr = unsafe { core::ptr::addr_of!(L1) } as u64;
+	unsafe {
+		asm!(
+			concat!("msr MAIR_", el!(), ", {mair}"),
+			concat!("msr TCR_", el!(), ", {tcr}"),
+			concat!("msr TTBR0_", el!(), ", {ttbr}"),
+			"dsb ish",
+			"isb",
+			tlbi_all!(),
+			"dsb ish",
+			"isb",
+			concat!("mrs {tmp}, SCTLR_", el!()),
+			"orr {tmp}, {tmp}, {set}",
+			"bic {tmp}, {tmp}, {clear}",
+			concat!("msr SCTLR_", el!(), ", {tmp}"),
+			"isb",
+			mair = in(reg) MAIR,
+			tcr = in(reg) TCR,
+			ttbr = in(reg) ttbr,
+			set = in(reg) SCTLR_M | SCTLR_C | SCTLR_I,
+			clear = in(reg) SCTLR_A,
+			tmp = out(reg) _,
+		);
+	}
+}
@@ -27,30 +27,34 @@ impl<A> RegField<A> {
 			offset,
 		}
 	}
+	// The field's bits, in place.  Shifting (instead of 2^(offset + size)) works for fields that go up to bit 31.
+	fn mask(&self) -> u32 {
+		(u32::MAX >> (32 - self.size)) << self.offset
+	}
 }
 impl RegField<ReadOnly> {
 	#[inline]
 	pub fn read(&self) -> u32 {
-		(unsafe { ptr::read_volatile(self.access.0) } >> self.offset) & (2u32.pow(self.size) - 1)
+		(unsafe { ptr::read_volatile(self.access.0) } & self.mask()) >> self.offset
 	}
 }
 impl RegField<WriteOnly> {
 	#[inline]
 	pub fn write(&mut self, v: u32) {
-		assert!(v < 2u32.pow(self.size));
+		assert!(v <= self.mask() >> self.offset);
 		unsafe { ptr::write_volatile(self.access.0, v << self.offset) }
 	}
 }
 impl RegField<ReadWrite> {
 	#[inline]
 	pub fn read(&self) -> u32 {
-		(unsafe { ptr::read_volatile(self.access.0) } >> self.offset) & (2u32.pow(self.size) - 1)
+		(unsafe { ptr::read_volatile(self.access.0) } & self.mask()) >> self.offset
 	}
 	#[inline]
 	pub fn write(&mut self, v: u32) {
-		assert!(v < 2u32.pow(self.size));
+		assert!(v <= self.mask() >> self.offset);
 		let v = v << self.offset;
-		let mask = !(2u32.pow(self.offset + self.size) - 2u32.pow(self.offset));
+		let mask = !self.mask();
 		let mut t = unsafe { ptr::read_volatile(self.access.0) };
 		t &= mask;
 		t |= v;
@@ -61,14 +65,14 @@ impl RegField<Shared> {
 	#[inline]
 	pub fn read(&self) -> u32 {
 		let t = unsafe { &*self.access.0 };
-		(t.load(Ordering::Acquire) >> self.offset) & (2u32.pow(self.size) - 1)
+		(t.load(Ordering::Acquire) & self.mask()) >> self.offset
 	}
 	#[inline]
 	pub fn write(&mut self, v: u32) {
-		assert!(v < 2u32.pow(self.size));
+		assert!(v <= self.mask() >> self.offset);
 		let t = unsafe { &*self.access.0 };
 		let v = v << self.offset;
-		let mask = !(2u32.pow(self.offset + self.size) - 2u32.pow(self.offset));
+		let mask = !self.mask();
 		t.fetch_update(Ordering::AcqRel, Ordering::Acquire, |mut t| {
 			t &= mask;
 			t |= v;
@@ -113,5 +117,16 @@ mod tests {
 			*test.get_mut(),
 			0b10_111_010_101_010_101_010_101_010_101_010
 		);
+
+		// Fields that go up to bit 31, and the whole register
+		let mut test = 0x1234_5678;
+		let mut reg = unsafe { RegField::new(ReadWrite(addr_of_mut!(test)), 4, 28) };
+		assert_eq!(reg.read(), 0x1);
+		reg.write(0xF);
+		assert_eq!(test, 0xF234_5678);
+		let mut reg = unsafe { RegField::new(ReadWrite(addr_of_mut!(test)), 32, 0) };
+		assert_eq!(reg.read(), 0xF234_5678);
+		reg.write(0xFFFF_FFFF);
+		assert_eq!(test, 0xFFFF_FFFF);
 	}
 }
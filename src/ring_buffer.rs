@@ -0,0 +1,102 @@
+use core::{
+	cell::UnsafeCell,
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+// A lock free byte queue for one producer and one consumer (like an IRQ handler and the code it interrupts).  head and tail count up forever and are only reduced mod N when indexing.
+pub struct RingBuffer<const N: usize> {
+	buffer: UnsafeCell<[u8; N]>,
+	// Only the producer writes head, and only the consumer writes tail
+	head: AtomicUsize,
+	tail: AtomicUsize,
+}
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> RingBuffer<N> {
+	pub const fn new() -> Self {
+		Self {
+			buffer: UnsafeCell::new([0; N]),
+			head: AtomicUsize::new(0),
+			tail: AtomicUsize::new(0),
+		}
+	}
+	pub fn len(&self) -> usize {
+		self.head
+			.load(Ordering::Acquire)
+			.wrapping_sub(self.tail.load(Ordering::Acquire))
+	}
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+	#[allow(unused)]
+	pub fn is_full(&self) -> bool {
+		self.len() == N
+	}
+
+	// Producer only.  Returns false if there wasn't room.
+	pub fn push(&self, b: u8) -> bool {
+		let head = self.head.load(Ordering::Relaxed);
+		if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == N {
+			return false;
+		}
+		unsafe { (*self.buffer.get())[head % N] = b };
+		self.head.store(head.wrapping_add(1), Ordering::Release);
+		true
+	}
+
+	// Consumer only
+	pub fn pop(&self) -> Option<u8> {
+		let tail = self.tail.load(Ordering::Relaxed);
+		if self.head.load(Ordering::Acquire) == tail {
+			return None;
+		}
+		let b = unsafe { (*self.buffer.get())[tail % N] };
+		self.tail.store(tail.wrapping_add(1), Ordering::Release);
+		Some(b)
+	}
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_push_pop() {
+		let ring = RingBuffer::<4>::new();
+		assert_eq!(ring.pop(), None);
+		for b in 0..4 {
+			assert!(ring.push(b));
+		}
+		assert!(ring.is_full() && !ring.push(4));
+		assert_eq!(ring.pop(), Some(0));
+		assert!(ring.push(4));
+		// Wraps around the end of the buffer
+		assert_eq!(
+			core::iter::from_fn(|| ring.pop()).collect::<Vec<_>>(),
+			[1, 2, 3, 4]
+		);
+		assert!(ring.is_empty());
+	}
+
+	#[test]
+	fn check_producer_consumer() {
+		static RING: RingBuffer<16> = RingBuffer::new();
+		let producer = std::thread::spawn(|| {
+			for i in 0..10_000u32 {
+				while !RING.push(i as u8) {
+					std::thread::yield_now();
+				}
+			}
+		});
+		for i in 0..10_000u32 {
+			let b = loop {
+				if let Some(b) = RING.pop() {
+					break b;
+				}
+				std::thread::yield_now();
+			};
+			assert_eq!(b, i as u8);
+		}
+		producer.join().unwrap();
+	}
+}
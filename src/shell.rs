@@ -0,0 +1,506 @@
+#![allow(dead_code)]
+
+// A tiny debug shell.  Drivers contribute commands by handing a static table of them to register, and the shell looks each line's first word up in everything that's been registered.
+use super::{
+	memory::{IO_BASE, LOCAL_BASE, LOCAL_SIZE},
+	sync::SpinLock,
+	uart::SerialPort,
+};
+use alloc::vec::Vec;
+use core::{
+	fmt::{self, Write},
+	ops::Range,
+	ptr,
+};
+
+const LINE_LEN: usize = 128;
+const PROMPT: &str = "> ";
+
+pub type CommandFn = fn(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError>;
+
+pub struct Command {
+	pub name: &'static str,
+	// The arguments, as shown by help
+	pub args: &'static str,
+	pub help: &'static str,
+	pub run: CommandFn,
+}
+// fn pointers that take references don't implement Debug or PartialEq, so go by the name.
+impl fmt::Debug for Command {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Command({})", self.name)
+	}
+}
+impl PartialEq for Command {
+	fn eq(&self, other: &Self) -> bool {
+		self.name == other.name
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandError {
+	// Wrong number of arguments, or an argument that doesn't make sense
+	Usage,
+	BadNumber,
+	Failed(&'static str),
+	Output,
+}
+impl From<fmt::Error> for CommandError {
+	fn from(_: fmt::Error) -> Self {
+		Self::Output
+	}
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ShellError {
+	UnknownCommand,
+	Command(&'static Command, CommandError),
+}
+
+// Later registrations win, so a driver can replace a builtin.
+pub struct CommandTable {
+	tables: Vec<&'static [Command]>,
+}
+impl CommandTable {
+	pub const fn new() -> Self {
+		Self { tables: Vec::new() }
+	}
+	pub fn register(&mut self, commands: &'static [Command]) {
+		self.tables.push(commands);
+	}
+	fn commands(&self) -> impl Iterator<Item = &'static Command> + '_ {
+		self.tables
+			.iter()
+			.rev()
+			.flat_map(|table| table.iter())
+			.chain(BUILTINS.iter())
+	}
+	pub fn find(&self, name: &str) -> Option<&'static Command> {
+		self.commands().find(|command| command.name == name)
+	}
+	pub fn write_help(&self, out: &mut dyn Write) -> fmt::Result {
+		writeln!(out, "{:<24}List the commands", "help")?;
+		for (i, command) in self.commands().enumerate() {
+			// Skip anything that's been replaced
+			if self.commands().position(|c| c.name == command.name) != Some(i) {
+				continue;
+			}
+			let usage = format_usage(command);
+			writeln!(out, "{:<24}{}", usage.as_str(), command.help)?;
+		}
+		Ok(())
+	}
+}
+
+static COMMANDS: SpinLock<CommandTable> = SpinLock::new(CommandTable::new());
+
+pub fn register(commands: &'static [Command]) {
+	COMMANDS.lock().register(commands);
+}
+
+// The name and arguments, padded for help.  Usage strings are short, so a fixed buffer is plenty.
+struct Usage {
+	buf: [u8; 48],
+	len: usize,
+}
+impl Usage {
+	fn as_str(&self) -> &str {
+		core::str::from_utf8(&self.buf[..self.len]).unwrap()
+	}
+}
+impl Write for Usage {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		let len = s.len().min(self.buf.len() - self.len);
+		self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+		self.len += len;
+		Ok(())
+	}
+}
+fn format_usage(command: &Command) -> Usage {
+	let mut usage = Usage {
+		buf: [0; 48],
+		len: 0,
+	};
+	let _ = write!(&mut usage, "{} {}", command.name, command.args);
+	usage
+}
+
+// Run one line against table.  The lock is only held for the lookup, so commands can take as long as they like with interrupts on.
+pub fn execute(
+	table: &SpinLock<CommandTable>,
+	line: &str,
+	out: &mut dyn Write,
+) -> Result<(), ShellError> {
+	let words: Vec<&str> = line.split_whitespace().collect();
+	let name = match words.first() {
+		Some(name) => *name,
+		None => return Ok(()),
+	};
+	if name == "help" {
+		let _ = table.lock().write_help(out);
+		return Ok(());
+	}
+	let command = table.lock().find(name).ok_or(ShellError::UnknownCommand)?;
+	(command.run)(&words[1..], out).map_err(|e| ShellError::Command(command, e))
+}
+
+// Read lines from serial and run them, forever.
+pub fn run<S: SerialPort>(serial: &mut S) -> ! {
+	let mut buf = [0; LINE_LEN];
+	loop {
+		let _ = serial.write_str(PROMPT);
+		let line = serial.read_line(&mut buf);
+		let result = execute(&COMMANDS, line, serial);
+		let _ = match result {
+			Ok(()) => Ok(()),
+			Err(ShellError::UnknownCommand) => writeln!(serial, "Unknown command (try help)"),
+			Err(ShellError::Command(command, CommandError::Usage)) => {
+				writeln!(serial, "Usage: {}", format_usage(command).as_str())
+			}
+			Err(ShellError::Command(command, CommandError::BadNumber)) => writeln!(
+				serial,
+				"{}: numbers are decimal, or hex / binary with 0x / 0b",
+				command.name
+			),
+			Err(ShellError::Command(command, CommandError::Failed(why))) => {
+				writeln!(serial, "{}: {}", command.name, why)
+			}
+			Err(ShellError::Command(_, CommandError::Output)) => Ok(()),
+		};
+	}
+}
+
+// Decimal, or hex / binary / octal with a 0x / 0b / 0o prefix.  Underscores are ignored.
+pub fn parse_number(s: &str) -> Result<u64, CommandError> {
+	let (digits, radix) = match s.get(..2) {
+		Some("0x") | Some("0X") => (&s[2..], 16),
+		Some("0b") | Some("0B") => (&s[2..], 2),
+		Some("0o") | Some("0O") => (&s[2..], 8),
+		_ => (s, 10),
+	};
+	let mut value: u64 = 0;
+	let mut any = false;
+	for c in digits.chars().filter(|c| *c != '_') {
+		let digit = c.to_digit(radix).ok_or(CommandError::BadNumber)?;
+		value = value
+			.checked_mul(radix as u64)
+			.and_then(|v| v.checked_add(digit as u64))
+			.ok_or(CommandError::BadNumber)?;
+		any = true;
+	}
+	if any {
+		Ok(value)
+	} else {
+		Err(CommandError::BadNumber)
+	}
+}
+
+// The nth argument as a number
+pub fn number_arg(args: &[&str], n: usize) -> Result<u64, CommandError> {
+	args.get(n)
+		.ok_or(CommandError::Usage)
+		.and_then(|arg| parse_number(arg))
+}
+
+// Lines of 16 bytes: the address, the bytes in hex, then the bytes as ASCII.
+pub fn write_hexdump(out: &mut dyn Write, base: usize, bytes: &[u8]) -> fmt::Result {
+	for (i, line) in bytes.chunks(16).enumerate() {
+		let addr = base.checked_add(i * 16).ok_or(fmt::Error)?;
+		write!(out, "{:08x} ", addr)?;
+		for col in 0..16 {
+			if col == 8 {
+				write!(out, " ")?;
+			}
+			match line.get(col) {
+				Some(b) => write!(out, " {:02x}", b)?,
+				None => write!(out, "   ")?,
+			}
+		}
+		write!(out, "  |")?;
+		for &b in line {
+			let c = if (0x20..0x7F).contains(&b) {
+				b as char
+			} else {
+				'.'
+			};
+			write!(out, "{}", c)?;
+		}
+		writeln!(out, "|")?;
+	}
+	Ok(())
+}
+
+// What paging::init maps: RAM, the legacy peripherals, and the ARM local peripherals.  Anything else is a translation fault, and the exception handler would return to the faulting load forever.
+const MAPPED: [Range<usize>; 3] = [
+	0..IO_BASE as usize,
+	IO_BASE as usize..LOCAL_BASE as usize,
+	LOCAL_BASE as usize..(LOCAL_BASE + LOCAL_SIZE) as usize,
+];
+
+fn is_mapped(ranges: &[Range<usize>], start: usize, len: usize) -> bool {
+	match start.checked_add(len) {
+		Some(end) => ranges.iter().any(|r| r.start <= start && end <= r.end),
+		None => false,
+	}
+}
+
+fn check_mapped(start: usize, len: usize) -> Result<(), CommandError> {
+	// The host tests look at their own memory, which is nowhere near the Pi's
+	let ranges: &[Range<usize>] = if cfg!(target_arch = "aarch64") {
+		&MAPPED
+	} else {
+		&[0..usize::MAX]
+	};
+	if is_mapped(ranges, start, len) {
+		Ok(())
+	} else {
+		Err(CommandError::Failed("that isn't RAM or a peripheral"))
+	}
+}
+
+fn word_address(args: &[&str]) -> Result<*mut u32, CommandError> {
+	let addr = number_arg(args, 0)? as usize;
+	if addr % 4 != 0 {
+		return Err(CommandError::Failed("the address has to be 4 byte aligned"));
+	}
+	check_mapped(addr, 4)?;
+	Ok(addr as *mut u32)
+}
+
+fn peek(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	if args.len() != 1 {
+		return Err(CommandError::Usage);
+	}
+	let addr = word_address(args)?;
+	let value = unsafe { ptr::read_volatile(addr) };
+	writeln!(out, "{:#010x}: {:#010x}", addr as usize, value)?;
+	Ok(())
+}
+
+fn poke(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	if args.len() != 2 {
+		return Err(CommandError::Usage);
+	}
+	let addr = word_address(args)?;
+	let value = number_arg(args, 1)?;
+	if value > u32::MAX as u64 {
+		return Err(CommandError::Failed("the value has to fit in 32 bits"));
+	}
+	unsafe { ptr::write_volatile(addr, value as u32) };
+	writeln!(out, "{:#010x} <- {:#010x}", addr as usize, value)?;
+	Ok(())
+}
+
+fn hexdump(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	if args.len() != 2 {
+		return Err(CommandError::Usage);
+	}
+	let base = number_arg(args, 0)? as usize;
+	let len = number_arg(args, 1)? as usize;
+	// This also makes sure that base + len doesn't overflow
+	check_mapped(base, len)?;
+	// Read a line at a time (with volatile reads, in case it's a peripheral)
+	let mut line = [0; 16];
+	for offset in (0..len).step_by(16) {
+		let count = (len - offset).min(16);
+		for (i, b) in line[..count].iter_mut().enumerate() {
+			*b = unsafe { ptr::read_volatile((base + offset + i) as *const u8) };
+		}
+		write_hexdump(out, base + offset, &line[..count])?;
+	}
+	Ok(())
+}
+
+static BUILTINS: [Command; 3] = [
+	Command {
+		name: "peek",
+		args: "<addr>",
+		help: "Read a 32 bit word",
+		run: peek,
+	},
+	Command {
+		name: "poke",
+		args: "<addr> <value>",
+		help: "Write a 32 bit word",
+		run: poke,
+	},
+	Command {
+		name: "hexdump",
+		args: "<addr> <len>",
+		help: "Dump memory as hex and ASCII",
+		run: hexdump,
+	},
+];
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+	use alloc::{format, string::String};
+
+	fn echo(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+		writeln!(out, "{}", args.join(","))?;
+		Ok(())
+	}
+	fn add(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+		if args.len() != 2 {
+			return Err(CommandError::Usage);
+		}
+		writeln!(out, "{}", number_arg(args, 0)? + number_arg(args, 1)?)?;
+		Ok(())
+	}
+	static TEST_COMMANDS: [Command; 2] = [
+		Command {
+			name: "echo",
+			args: "<words...>",
+			help: "Print the arguments",
+			run: echo,
+		},
+		Command {
+			name: "add",
+			args: "<a> <b>",
+			help: "Add two numbers",
+			run: add,
+		},
+	];
+	// Replaces peek
+	static OVERRIDES: [Command; 1] = [Command {
+		name: "peek",
+		args: "",
+		help: "Not actually peek",
+		run: echo,
+	}];
+
+	fn run_line(table: &SpinLock<CommandTable>, line: &str) -> (Result<(), ShellError>, String) {
+		let mut out = String::new();
+		let result = execute(table, line, &mut out);
+		(result, out)
+	}
+
+	#[test]
+	fn check_parse_number() {
+		assert_eq!(parse_number("1234"), Ok(1234));
+		assert_eq!(parse_number("0x3F20_0000"), Ok(0x3F20_0000));
+		assert_eq!(parse_number("0b1010"), Ok(10));
+		assert_eq!(parse_number("0o17"), Ok(15));
+		assert_eq!(parse_number("0xffffffffffffffff"), Ok(u64::MAX));
+		assert_eq!(
+			parse_number("0x1_0000_0000_0000_0000"),
+			Err(CommandError::BadNumber)
+		);
+		assert_eq!(parse_number("0x"), Err(CommandError::BadNumber));
+		assert_eq!(parse_number("12a"), Err(CommandError::BadNumber));
+		assert_eq!(number_arg(&["1"], 1), Err(CommandError::Usage));
+	}
+
+	#[test]
+	fn check_execute() {
+		let table = SpinLock::new(CommandTable::new());
+		table.lock().register(&TEST_COMMANDS);
+
+		assert_eq!(
+			run_line(&table, "  echo a   b c "),
+			(Ok(()), "a,b,c\n".into())
+		);
+		assert_eq!(run_line(&table, "add 0x10 1"), (Ok(()), "17\n".into()));
+		assert_eq!(run_line(&table, ""), (Ok(()), "".into()));
+		assert_eq!(run_line(&table, "nope").0, Err(ShellError::UnknownCommand));
+		assert_eq!(
+			run_line(&table, "add 1").0,
+			Err(ShellError::Command(&TEST_COMMANDS[1], CommandError::Usage))
+		);
+		assert_eq!(
+			run_line(&table, "add 1 two").0,
+			Err(ShellError::Command(
+				&TEST_COMMANDS[1],
+				CommandError::BadNumber
+			))
+		);
+
+		// Builtins are there, until something replaces them
+		assert_eq!(
+			table.lock().find("peek").unwrap().help,
+			"Read a 32 bit word"
+		);
+		table.lock().register(&OVERRIDES);
+		assert_eq!(run_line(&table, "peek 1 2"), (Ok(()), "1,2\n".into()));
+
+		let (result, help) = run_line(&table, "help");
+		assert_eq!(result, Ok(()));
+		assert!(help.starts_with("help "));
+		assert!(help.contains(&format!("\n{:<24}Add two numbers\n", "add <a> <b>")));
+		assert!(help.contains("Not actually peek"));
+		assert!(!help.contains("Read a 32 bit word"));
+		assert!(help.contains("Write a 32 bit word"));
+	}
+
+	#[test]
+	fn check_peek_poke() {
+		let table = SpinLock::new(CommandTable::new());
+		let mut word = 0x1234_5678u32;
+		let addr = &mut word as *mut u32 as usize;
+
+		let (result, out) = run_line(&table, &format!("peek {:#x}", addr));
+		assert_eq!(result, Ok(()));
+		assert_eq!(out, format!("{:#010x}: 0x12345678\n", addr));
+
+		let (result, _) = run_line(&table, &format!("poke {:#x} 0xCAFE_F00D", addr));
+		assert_eq!(result, Ok(()));
+		assert_eq!(unsafe { ptr::read_volatile(&word) }, 0xCAFE_F00D);
+
+		assert_eq!(
+			run_line(&table, &format!("peek {:#x}", addr + 1)).0,
+			Err(ShellError::Command(
+				&BUILTINS[0],
+				CommandError::Failed("the address has to be 4 byte aligned")
+			))
+		);
+		assert_eq!(
+			run_line(&table, &format!("poke {:#x} 0x1_0000_0000", addr)).0,
+			Err(ShellError::Command(
+				&BUILTINS[1],
+				CommandError::Failed("the value has to fit in 32 bits")
+			))
+		);
+	}
+
+	#[test]
+	fn check_mapped_ranges() {
+		assert!(is_mapped(&MAPPED, 0x8_0000, 4));
+		assert!(is_mapped(&MAPPED, 0x3F20_0000, 4));
+		assert!(is_mapped(&MAPPED, 0x4003_FFFC, 4));
+		assert!(!is_mapped(&MAPPED, 0x4004_0000, 4));
+		assert!(!is_mapped(&MAPPED, 0x4003_FFFC, 8));
+		assert!(!is_mapped(&MAPPED, 0x8000_0000, 4));
+		// Wrapping around the top doesn't get back to RAM
+		assert!(!is_mapped(&MAPPED, usize::MAX - 1, 4));
+
+		let table = SpinLock::new(CommandTable::new());
+		assert_eq!(
+			run_line(&table, "hexdump 0xFFFF_FFFF_FFFF_FFF0 0x20").0,
+			Err(ShellError::Command(
+				&BUILTINS[2],
+				CommandError::Failed("that isn't RAM or a peripheral")
+			))
+		);
+	}
+
+	#[test]
+	fn check_hexdump() {
+		let mut out = String::new();
+		write_hexdump(&mut out, 0x8_0000, b"Hello, world!\n\x00\x01\xFFabc").unwrap();
+		assert_eq!(
+			out,
+			"00080000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 01  |Hello, world!...|\n\
+			 00080010  ff 61 62 63                                       |.abc|\n"
+		);
+
+		let table = SpinLock::new(CommandTable::new());
+		let bytes = *b"0123456789abcdefXYZ";
+		let addr = bytes.as_ptr() as usize;
+		let (result, out) = run_line(&table, &format!("hexdump {:#x} 19", addr));
+		assert_eq!(result, Ok(()));
+		let mut expected = String::new();
+		write_hexdump(&mut expected, addr, &bytes).unwrap();
+		assert_eq!(out, expected);
+	}
+}
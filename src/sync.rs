@@ -0,0 +1,85 @@
+use core::{
+	cell::UnsafeCell,
+	hint::spin_loop,
+	ops::{Deref, DerefMut},
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+// A spin lock that also masks IRQs and FIQs on the calling core while it's held, so that an interrupt handler can't deadlock against the code it interrupted.
+pub struct SpinLock<T> {
+	locked: AtomicBool,
+	data: UnsafeCell<T>,
+}
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T> {
+	lock: &'a SpinLock<T>,
+	daif: u64,
+}
+
+impl<T> SpinLock<T> {
+	pub const fn new(data: T) -> Self {
+		Self {
+			locked: AtomicBool::new(false),
+			data: UnsafeCell::new(data),
+		}
+	}
+	pub fn lock(&self) -> SpinLockGuard<'_, T> {
+		let daif = mask_interrupts();
+		while self
+			.locked
+			.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+			.is_err()
+		{
+			while self.locked.load(Ordering::Relaxed) {
+				spin_loop();
+			}
+		}
+		SpinLockGuard { lock: self, daif }
+	}
+	// SAFETY: Whoever is holding the lock has to never touch the data again, or we have to be going down anyway.
+	pub unsafe fn force_unlock(&self) {
+		self.locked.store(false, Ordering::Release);
+	}
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+	type Target = T;
+	fn deref(&self) -> &T {
+		unsafe { &*self.lock.data.get() }
+	}
+}
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { &mut *self.lock.data.get() }
+	}
+}
+impl<T> Drop for SpinLockGuard<'_, T> {
+	fn drop(&mut self) {
+		self.lock.locked.store(false, Ordering::Release);
+		restore_interrupts(self.daif);
+	}
+}
+
+// Returns the old DAIF so that nested locks only unmask once the outermost one is dropped.
+#[cfg(target_arch = "aarch64")]
+fn mask_interrupts() -> u64 {
+	let daif: u64;
+	unsafe {
+		asm!("mrs {}, DAIF", "msr DAIFSet, #0b0011", out(reg) daif);
+	}
+	daif
+}
+#[cfg(target_arch = "aarch64")]
+fn restore_interrupts(daif: u64) {
+	unsafe {
+		asm!("msr DAIF, {}", in(reg) daif);
+	}
+}
+#[cfg(not(target_arch = "aarch64"))]
+fn mask_interrupts() -> u64 {
+	0
+}
+#[cfg(not(target_arch = "aarch64"))]
+fn restore_interrupts(_daif: u64) {}
@@ -0,0 +1,395 @@
+// Decoding for the Exception Syndrome Register (ESR_ELx).  The field layouts are from the ARM ARM: D13.2.37
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExceptionClass {
+	Unknown,
+	WfiWfe,
+	FpAccess,
+	IllegalExecutionState,
+	Svc32,
+	Svc64,
+	Hvc64,
+	Smc64,
+	SysRegTrap,
+	InstructionAbortLower,
+	InstructionAbortCurrent,
+	PcAlignment,
+	DataAbortLower,
+	DataAbortCurrent,
+	SpAlignment,
+	FpException32,
+	FpException64,
+	SError,
+	BreakpointLower,
+	BreakpointCurrent,
+	SoftwareStepLower,
+	SoftwareStepCurrent,
+	WatchpointLower,
+	WatchpointCurrent,
+	Brk64,
+	Other(u8),
+}
+impl From<u8> for ExceptionClass {
+	fn from(ec: u8) -> Self {
+		match ec {
+			0x00 => Self::Unknown,
+			0x01 => Self::WfiWfe,
+			0x07 => Self::FpAccess,
+			0x0E => Self::IllegalExecutionState,
+			0x11 => Self::Svc32,
+			0x15 => Self::Svc64,
+			0x16 => Self::Hvc64,
+			0x17 => Self::Smc64,
+			0x18 => Self::SysRegTrap,
+			0x20 => Self::InstructionAbortLower,
+			0x21 => Self::InstructionAbortCurrent,
+			0x22 => Self::PcAlignment,
+			0x24 => Self::DataAbortLower,
+			0x25 => Self::DataAbortCurrent,
+			0x26 => Self::SpAlignment,
+			0x28 => Self::FpException32,
+			0x2C => Self::FpException64,
+			0x2F => Self::SError,
+			0x30 => Self::BreakpointLower,
+			0x31 => Self::BreakpointCurrent,
+			0x32 => Self::SoftwareStepLower,
+			0x33 => Self::SoftwareStepCurrent,
+			0x34 => Self::WatchpointLower,
+			0x35 => Self::WatchpointCurrent,
+			0x3C => Self::Brk64,
+			ec => Self::Other(ec),
+		}
+	}
+}
+impl fmt::Display for ExceptionClass {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Unknown => write!(f, "Unknown reason"),
+			Self::WfiWfe => write!(f, "Trapped WFI / WFE"),
+			Self::FpAccess => write!(f, "Trapped FP / SIMD access"),
+			Self::IllegalExecutionState => write!(f, "Illegal execution state"),
+			Self::Svc32 => write!(f, "SVC (aarch32)"),
+			Self::Svc64 => write!(f, "SVC"),
+			Self::Hvc64 => write!(f, "HVC"),
+			Self::Smc64 => write!(f, "SMC"),
+			Self::SysRegTrap => write!(f, "Trapped system register access"),
+			Self::InstructionAbortLower => write!(f, "Instruction abort from a lower EL"),
+			Self::InstructionAbortCurrent => write!(f, "Instruction abort from the current EL"),
+			Self::PcAlignment => write!(f, "PC alignment fault"),
+			Self::DataAbortLower => write!(f, "Data abort from a lower EL"),
+			Self::DataAbortCurrent => write!(f, "Data abort from the current EL"),
+			Self::SpAlignment => write!(f, "SP alignment fault"),
+			Self::FpException32 => write!(f, "Floating point exception (aarch32)"),
+			Self::FpException64 => write!(f, "Floating point exception"),
+			Self::SError => write!(f, "SError"),
+			Self::BreakpointLower => write!(f, "Breakpoint from a lower EL"),
+			Self::BreakpointCurrent => write!(f, "Breakpoint from the current EL"),
+			Self::SoftwareStepLower => write!(f, "Software step from a lower EL"),
+			Self::SoftwareStepCurrent => write!(f, "Software step from the current EL"),
+			Self::WatchpointLower => write!(f, "Watchpoint from a lower EL"),
+			Self::WatchpointCurrent => write!(f, "Watchpoint from the current EL"),
+			Self::Brk64 => write!(f, "BRK"),
+			Self::Other(ec) => write!(f, "Exception class {:#04x}", ec),
+		}
+	}
+}
+
+// The DFSC / IFSC field of a data / instruction abort.  Faults that happen during a table walk carry the level of the walk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultStatus {
+	AddressSize(u8),
+	Translation(u8),
+	AccessFlag(u8),
+	Permission(u8),
+	SyncExternal,
+	SyncExternalWalk(u8),
+	SyncParity,
+	SyncParityWalk(u8),
+	Alignment,
+	TlbConflict,
+	UnsupportedExclusive,
+	Other(u8),
+}
+impl From<u8> for FaultStatus {
+	fn from(fsc: u8) -> Self {
+		let level = fsc & 0b11;
+		match fsc >> 2 {
+			0b0000 => Self::AddressSize(level),
+			0b0001 => Self::Translation(level),
+			0b0010 => Self::AccessFlag(level),
+			0b0011 => Self::Permission(level),
+			0b0101 => Self::SyncExternalWalk(level),
+			0b0111 => Self::SyncParityWalk(level),
+			_ => match fsc {
+				0b010000 => Self::SyncExternal,
+				0b011000 => Self::SyncParity,
+				0b100001 => Self::Alignment,
+				0b110000 => Self::TlbConflict,
+				0b110101 => Self::UnsupportedExclusive,
+				fsc => Self::Other(fsc),
+			},
+		}
+	}
+}
+impl fmt::Display for FaultStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::AddressSize(l) => write!(f, "address size fault (level {})", l),
+			Self::Translation(l) => write!(f, "translation fault (level {})", l),
+			Self::AccessFlag(l) => write!(f, "access flag fault (level {})", l),
+			Self::Permission(l) => write!(f, "permission fault (level {})", l),
+			Self::SyncExternal => write!(f, "synchronous external abort"),
+			Self::SyncExternalWalk(l) => {
+				write!(f, "synchronous external abort on table walk (level {})", l)
+			}
+			Self::SyncParity => write!(f, "synchronous parity / ECC error"),
+			Self::SyncParityWalk(l) => {
+				write!(
+					f,
+					"synchronous parity / ECC error on table walk (level {})",
+					l
+				)
+			}
+			Self::Alignment => write!(f, "alignment fault"),
+			Self::TlbConflict => write!(f, "TLB conflict abort"),
+			Self::UnsupportedExclusive => write!(f, "unsupported exclusive access"),
+			Self::Other(fsc) => write!(f, "fault status {:#08b}", fsc),
+		}
+	}
+}
+
+// The register that a faulting load / store was using.  Only available when ISS.ISV is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Access {
+	// Size of the access in bytes
+	pub size: u8,
+	pub register: u8,
+	pub sign_extend: bool,
+	// 64bit (x) register vs 32bit (w) register
+	pub sixty_four: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Abort {
+	pub status: FaultStatus,
+	// WnR: Always false for instruction aborts
+	pub write: bool,
+	pub access: Option<Access>,
+	// FnV clear: FAR holds the faulting address
+	pub far_valid: bool,
+	// S1PTW: The fault happened on a stage 2 walk for a stage 1 table
+	pub on_table_walk: bool,
+}
+
+// The Instruction Specific Syndrome, decoded according to the exception class.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Iss {
+	// The immediate of an SVC, HVC, or SMC
+	Call(u16),
+	// The comment of a BRK
+	Breakpoint(u16),
+	DataAbort(Abort),
+	InstructionAbort(Abort),
+	Raw(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Syndrome(pub u64);
+impl Syndrome {
+	pub fn class(&self) -> ExceptionClass {
+		ExceptionClass::from(((self.0 >> 26) & 0b111111) as u8)
+	}
+	// The length of the instruction that trapped in bytes.
+	pub fn instruction_length(&self) -> u8 {
+		if self.0 & (1 << 25) != 0 {
+			4
+		} else {
+			2
+		}
+	}
+	pub fn iss(&self) -> u32 {
+		(self.0 & 0x1FF_FFFF) as u32
+	}
+	pub fn decode(&self) -> Iss {
+		let iss = self.iss();
+		let bit = |n: u32| iss & (1 << n) != 0;
+		match self.class() {
+			ExceptionClass::Svc32
+			| ExceptionClass::Svc64
+			| ExceptionClass::Hvc64
+			| ExceptionClass::Smc64 => Iss::Call(iss as u16),
+			ExceptionClass::Brk64 => Iss::Breakpoint(iss as u16),
+			ExceptionClass::DataAbortLower | ExceptionClass::DataAbortCurrent => {
+				Iss::DataAbort(Abort {
+					status: FaultStatus::from((iss & 0b111111) as u8),
+					write: bit(6),
+					access: if bit(24) {
+						Some(Access {
+							size: 1 << ((iss >> 22) & 0b11),
+							register: ((iss >> 16) & 0b11111) as u8,
+							sign_extend: bit(21),
+							sixty_four: bit(15),
+						})
+					} else {
+						None
+					},
+					far_valid: !bit(10),
+					on_table_walk: bit(7),
+				})
+			}
+			ExceptionClass::InstructionAbortLower | ExceptionClass::InstructionAbortCurrent => {
+				Iss::InstructionAbort(Abort {
+					status: FaultStatus::from((iss & 0b111111) as u8),
+					write: false,
+					access: None,
+					far_valid: !bit(10),
+					on_table_walk: bit(7),
+				})
+			}
+			_ => Iss::Raw(iss),
+		}
+	}
+}
+impl fmt::Display for Syndrome {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.class())?;
+		match self.decode() {
+			Iss::Call(imm) => write!(f, " #{:#x}", imm),
+			Iss::Breakpoint(comment) => write!(f, " #{:#x}", comment),
+			Iss::DataAbort(abort) => {
+				write!(f, ": {}", abort.status)?;
+				let direction = if abort.write { "write" } else { "read" };
+				match abort.access {
+					Some(access) => write!(
+						f,
+						", {} of {} bytes using {}{}",
+						direction,
+						access.size,
+						if access.sixty_four { 'x' } else { 'w' },
+						access.register
+					)?,
+					None => write!(f, ", {}", direction)?,
+				}
+				if !abort.far_valid {
+					write!(f, ", FAR is not valid")?;
+				}
+				Ok(())
+			}
+			Iss::InstructionAbort(abort) => {
+				write!(f, ": {}", abort.status)?;
+				if !abort.far_valid {
+					write!(f, ", FAR is not valid")?;
+				}
+				Ok(())
+			}
+			Iss::Raw(iss) => write!(f, " (ISS: {:#x})", iss),
+		}
+	}
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_exception_classes() {
+		// svc #42
+		let svc = Syndrome(0x5600_002A);
+		assert_eq!(svc.class(), ExceptionClass::Svc64);
+		assert_eq!(svc.instruction_length(), 4);
+		assert_eq!(svc.decode(), Iss::Call(42));
+
+		// brk #0x3e8
+		let brk = Syndrome(0xF200_03E8);
+		assert_eq!(brk.class(), ExceptionClass::Brk64);
+		assert_eq!(brk.decode(), Iss::Breakpoint(1000));
+
+		assert_eq!(Syndrome(0xBE00_0000).class(), ExceptionClass::SError);
+		assert_eq!(Syndrome(0x8A00_0000).class(), ExceptionClass::PcAlignment);
+		assert_eq!(Syndrome(0x9A00_0000).class(), ExceptionClass::SpAlignment);
+		assert_eq!(Syndrome(0x1E00_0000).class(), ExceptionClass::FpAccess);
+		assert_eq!(Syndrome(0xB200_0000).class(), ExceptionClass::FpException64);
+		assert_eq!(Syndrome(0x0E00_0000).class(), ExceptionClass::Other(0x03));
+	}
+
+	#[test]
+	fn check_fault_status() {
+		assert_eq!(FaultStatus::from(0b000110), FaultStatus::Translation(2));
+		assert_eq!(FaultStatus::from(0b001011), FaultStatus::AccessFlag(3));
+		assert_eq!(FaultStatus::from(0b001101), FaultStatus::Permission(1));
+		assert_eq!(FaultStatus::from(0b010000), FaultStatus::SyncExternal);
+		assert_eq!(
+			FaultStatus::from(0b010101),
+			FaultStatus::SyncExternalWalk(1)
+		);
+		assert_eq!(FaultStatus::from(0b100001), FaultStatus::Alignment);
+		assert_eq!(
+			FaultStatus::from(0b110101),
+			FaultStatus::UnsupportedExclusive
+		);
+		assert_eq!(FaultStatus::from(0b111111), FaultStatus::Other(0b111111));
+	}
+
+	#[test]
+	fn check_data_abort() {
+		// str x3, [x1] to an unmapped address: ISV, 8 bytes, x3, 64bit, write, level 1 translation fault
+		let esr = (0x25 << 26)
+			| (1 << 25)
+			| (1 << 24)
+			| (0b11 << 22)
+			| (3 << 16)
+			| (1 << 15)
+			| (1 << 6)
+			| 0b000101;
+		let syndrome = Syndrome(esr);
+		assert_eq!(
+			syndrome.decode(),
+			Iss::DataAbort(Abort {
+				status: FaultStatus::Translation(1),
+				write: true,
+				access: Some(Access {
+					size: 8,
+					register: 3,
+					sign_extend: false,
+					sixty_four: true,
+				}),
+				far_valid: true,
+				on_table_walk: false,
+			})
+		);
+
+		// Instruction abort from a lower EL, with FnV set
+		let syndrome = Syndrome((0x20 << 26) | (1 << 25) | (1 << 10) | 0b001111);
+		assert_eq!(
+			syndrome.decode(),
+			Iss::InstructionAbort(Abort {
+				status: FaultStatus::Permission(3),
+				write: false,
+				access: None,
+				far_valid: false,
+				on_table_walk: false,
+			})
+		);
+	}
+
+	#[test]
+	fn check_display() {
+		use std::string::ToString;
+
+		assert_eq!(Syndrome(0x5600_002A).to_string(), "SVC #0x2a");
+		let esr = (0x25 << 26) | (1 << 25) | (1 << 24) | (0b10 << 22) | (7 << 16) | 0b100001;
+		assert_eq!(
+			Syndrome(esr).to_string(),
+			"Data abort from the current EL: alignment fault, read of 4 bytes using w7"
+		);
+		assert_eq!(
+			Syndrome((0x21 << 26) | (1 << 25) | 0b000111).to_string(),
+			"Instruction abort from the current EL: translation fault (level 3)"
+		);
+		assert_eq!(
+			Syndrome((0x18 << 26) | (1 << 25) | 0x1234).to_string(),
+			"Trapped system register access (ISS: 0x1234)"
+		);
+	}
+}
@@ -0,0 +1,218 @@
+#![allow(dead_code)]
+
+use super::{
+	generic_timer,
+	interrupts::{self, IrqSource},
+	memory::timer::*,
+	shell::{Command, CommandError},
+};
+use core::{
+	fmt::Write,
+	hint::spin_loop,
+	ops::{Add, AddAssign, Sub},
+	ptr,
+	sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+// The system timer is a free running 64bit counter that ticks at 1MHz, so everything here is in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Duration(u64);
+
+impl Duration {
+	pub const fn from_micros(us: u64) -> Self {
+		Self(us)
+	}
+	pub const fn from_millis(ms: u64) -> Self {
+		Self(ms * 1000)
+	}
+	pub const fn from_secs(s: u64) -> Self {
+		Self(s * 1_000_000)
+	}
+	pub const fn as_micros(&self) -> u64 {
+		self.0
+	}
+	pub const fn as_millis(&self) -> u64 {
+		self.0 / 1000
+	}
+}
+impl Add for Duration {
+	type Output = Duration;
+	fn add(self, rhs: Duration) -> Duration {
+		Duration(self.0 + rhs.0)
+	}
+}
+
+impl Instant {
+	pub fn now() -> Self {
+		now()
+	}
+	pub const fn as_micros(&self) -> u64 {
+		self.0
+	}
+	// Saturates to zero if earlier is actually later.
+	pub fn duration_since(&self, earlier: Instant) -> Duration {
+		Duration(self.0.saturating_sub(earlier.0))
+	}
+	pub fn elapsed(&self) -> Duration {
+		now().duration_since(*self)
+	}
+}
+impl Add<Duration> for Instant {
+	type Output = Instant;
+	fn add(self, rhs: Duration) -> Instant {
+		Instant(self.0 + rhs.0)
+	}
+}
+impl AddAssign<Duration> for Instant {
+	fn add_assign(&mut self, rhs: Duration) {
+		self.0 += rhs.0;
+	}
+}
+impl Sub for Instant {
+	type Output = Duration;
+	fn sub(self, rhs: Instant) -> Duration {
+		self.duration_since(rhs)
+	}
+}
+
+pub fn now() -> Instant {
+	// The counter is read 32 bits at a time, so if HI changed while we were reading LO then LO wrapped and we have to try again.
+	loop {
+		let hi = unsafe { ptr::read_volatile(TIMER_COUNTER_HI) };
+		let lo = unsafe { ptr::read_volatile(TIMER_COUNTER_LO) };
+		if unsafe { ptr::read_volatile(TIMER_COUNTER_HI) } == hi {
+			return Instant(((hi as u64) << 32) | lo as u64);
+		}
+	}
+}
+
+pub fn sleep(duration: Duration) {
+	let deadline = now() + duration;
+	while now() < deadline {
+		spin_loop();
+	}
+}
+
+pub fn sleep_us(us: u64) {
+	sleep(Duration::from_micros(us));
+}
+
+// Compare channels 0 and 2 are used by the GPU, which leaves 1 and 3 for us.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmChannel {
+	One = 1,
+	Three = 3,
+}
+impl AlarmChannel {
+	fn compare(self) -> *mut u32 {
+		match self {
+			Self::One => TIMER_COMPARE_1,
+			Self::Three => TIMER_COMPARE_3,
+		}
+	}
+	// The GPU interrupt number is the same as the channel number.
+	fn irq(self) -> IrqSource {
+		IrqSource::Gpu(self as u8)
+	}
+	fn slot(self) -> usize {
+		match self {
+			Self::One => 0,
+			Self::Three => 1,
+		}
+	}
+}
+
+// Callbacks are fn pointers stored as usize, 0 means no alarm is set.
+static ALARM_CALLBACKS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static ALARM_DEADLINES: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+// Whether each channel's IRQ handler has been registered.  cancel_alarm only disables the IRQ, so the next set_alarm just has to enable it again.
+static ALARM_HANDLED: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+// Call callback (from the IRQ handler) once deadline has passed.  Setting an alarm on a channel that already has one replaces it.
+pub fn set_alarm(channel: AlarmChannel, deadline: Instant, callback: fn()) {
+	ALARM_DEADLINES[channel.slot()].store(deadline.0, Ordering::Relaxed);
+	ALARM_CALLBACKS[channel.slot()].store(callback as usize, Ordering::Release);
+	if ALARM_HANDLED[channel.slot()].swap(true, Ordering::AcqRel) {
+		interrupts::enable_irq(channel.irq());
+	} else {
+		interrupts::register_handler(channel.irq(), move || alarm_fired(channel));
+	}
+	clear_match(channel);
+
+	// The compare registers only match against the low 32 bits of the counter.  If the deadline is already behind us, then aim just ahead of the counter instead, and keep trying until the match is in the future or it's already happened.
+	loop {
+		let target = deadline.max(now() + Duration::from_micros(2));
+		unsafe { ptr::write_volatile(channel.compare(), target.0 as u32) };
+		if now() < target || matched(channel) {
+			break;
+		}
+	}
+}
+
+pub fn cancel_alarm(channel: AlarmChannel) {
+	interrupts::disable_irq(channel.irq());
+	ALARM_CALLBACKS[channel.slot()].store(0, Ordering::Release);
+	clear_match(channel);
+}
+
+fn matched(channel: AlarmChannel) -> bool {
+	unsafe { ptr::read_volatile(TIMER_CONTROL_STATUS) & (1 << channel as u32) != 0 }
+}
+
+fn clear_match(channel: AlarmChannel) {
+	// Match bits are write 1 to clear
+	unsafe { ptr::write_volatile(TIMER_CONTROL_STATUS, 1 << channel as u32) };
+}
+
+fn alarm_fired(channel: AlarmChannel) {
+	clear_match(channel);
+	// A deadline more than 2^32us out matches early, when only the low 32 bits line up.  The compare register still holds the right value, so just wait for the next match.
+	if now().0 < ALARM_DEADLINES[channel.slot()].load(Ordering::Relaxed) {
+		return;
+	}
+	let callback = ALARM_CALLBACKS[channel.slot()].swap(0, Ordering::AcqRel);
+	if callback != 0 {
+		let callback: fn() = unsafe { core::mem::transmute(callback) };
+		callback();
+	}
+}
+
+fn timer_command(args: &[&str], out: &mut dyn Write) -> Result<(), CommandError> {
+	if !args.is_empty() {
+		return Err(CommandError::Usage);
+	}
+	let now = now();
+	writeln!(
+		out,
+		"Up for {}.{:06}s",
+		now.0 / 1_000_000,
+		now.0 % 1_000_000
+	)?;
+	writeln!(
+		out,
+		"Generic timer: {} ticks at {}Hz",
+		generic_timer::physical_count(),
+		generic_timer::frequency()
+	)?;
+	for channel in [AlarmChannel::One, AlarmChannel::Three].iter() {
+		if ALARM_CALLBACKS[channel.slot()].load(Ordering::Acquire) != 0 {
+			let deadline = ALARM_DEADLINES[channel.slot()].load(Ordering::Relaxed);
+			writeln!(
+				out,
+				"Alarm {:?} in {}us",
+				channel,
+				deadline.saturating_sub(now.0)
+			)?;
+		}
+	}
+	Ok(())
+}
+
+pub static COMMANDS: [Command; 1] = [Command {
+	name: "timer",
+	args: "",
+	help: "Uptime, the generic timer, and pending alarms",
+	run: timer_command,
+}];
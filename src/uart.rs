@@ -1,72 +1,223 @@
-use super::{
-	delay,
-	gpio::{self, Gpio},
-	set_bits,
-};
+// The two UARTs, and what they share: the SerialPort trait the console uses, and the interrupt driven buffers.  The one the console is on (see ConsoleUart) is picked at build time, so the other driver is only there for anyone who wants to switch to it with release.
+use super::{gpio::Pin, ring_buffer::RingBuffer};
 use core::{
 	fmt::{self, Write},
 	hint::spin_loop,
-	ptr,
+	sync::atomic::{AtomicBool, Ordering},
 };
 
-use super::memory::uart::*;
-
-pub struct Uart1;
-impl Uart1 {
-	pub fn new() -> Self {
-		// set GPIO15 and GPIO14 to AUX5
-		Gpio::new(14).configure(gpio::Func::Alt5);
-		Gpio::new(15).configure(gpio::Func::Alt5);
-		unsafe {
-			// set baud rate to 115200
-			set_bits(AUX_MU_BAUD, 0..16, 270);
-			// set the data size to 8 bit
-			*AUX_MU_LCR_REG = 0b11;
-			// Give a little delay so that the aux can take effect? I guess?
-			delay(150);
-			// Enable the mini uart
-			*AUX_MU_CNTL_REG = 0b_0_0_00_0_0_1_1;
+#[cfg_attr(feature = "pl011", allow(dead_code))]
+mod mini;
+#[cfg_attr(not(feature = "pl011"), allow(dead_code))]
+mod pl011;
+pub use mini::{mini_uart_divisor, Uart1, Uart1Pins, DEFAULT_CORE_CLOCK};
+pub use pl011::{pl011_divisor, Config, Parity, StopBits, Uart0, Uart0Pins, UART0_DEFAULT_CLOCK};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UartError {
+	// The closest the divisor can get is too far off of the baud rate that was asked for
+	UnreachableBaud { baud: u32, closest: u32 },
+	DataBits(u8),
+	// Something else has this GPIO pin (see the pins shell command)
+	PinTaken(u8),
+	// This pin can't be connected to the UART
+	WrongPin(u8),
+	// Flow control needs the CTS / RTS pins too
+	NoFlowControlPins,
+}
+
+fn check_pin<M>(pin: &Pin<M>, expected: u8) -> Result<(), UartError> {
+	if pin.pin() == expected {
+		Ok(())
+	} else {
+		Err(UartError::WrongPin(pin.pin()))
+	}
+}
+
+// What the console needs from a UART, so that it can be bound to either one.
+pub trait SerialPort: Write {
+	// Blocks until there's room in the transmit FIFO (or buffer)
+	fn write_byte(&mut self, b: u8);
+	fn try_read(&mut self) -> Option<u8>;
+	// Blocks until everything has been sent
+	fn flush(&mut self);
+
+	fn read_byte(&mut self) -> u8 {
+		loop {
+			if let Some(b) = self.try_read() {
+				return b;
+			}
+			spin_loop();
 		}
-		Self {}
 	}
-	fn transmit_ready(&self) -> bool {
-		let s = unsafe { ptr::read_volatile(AUX_MU_STAT_REG) };
-		s & 0b10 != 0
+
+	// Read a line into buf, echoing it back and handling backspace.  Only printable ASCII is kept, and anything past the end of buf is dropped.
+	fn read_line<'a>(&mut self, buf: &'a mut [u8]) -> &'a str {
+		let mut len = 0;
+		loop {
+			match self.read_byte() {
+				b'\r' | b'\n' => break,
+				// Backspace / delete
+				0x08 | 0x7F if len > 0 => {
+					len -= 1;
+					let _ = self.write_str("\x08 \x08");
+				}
+				b @ 0x20..=0x7E if len < buf.len() => {
+					buf[len] = b;
+					len += 1;
+					self.write_byte(b);
+				}
+				_ => {}
+			}
+		}
+		let _ = self.write_str("\n");
+		core::str::from_utf8(&buf[..len]).unwrap()
 	}
-	// If queue_byte is called when the transmit queue is full, the byte will be lost.
-	fn queue_byte(&mut self, b: u8) {
-		unsafe { ptr::write_volatile(AUX_MU_IO_REG, b as u32) };
+}
+
+// Send out each byte, replacing \n with \r\n
+fn write_serial<S: SerialPort + ?Sized>(port: &mut S, s: &str) -> fmt::Result {
+	for b in s.bytes() {
+		if b == b'\n' {
+			port.write_byte(b'\r');
+		}
+		port.write_byte(b);
+	}
+	Ok(())
+}
+
+// The hardware half of a UART, as seen by the interrupt driven buffers.
+trait Fifo {
+	fn tx_full() -> bool;
+	fn rx_empty() -> bool;
+	fn put(b: u8);
+	fn get() -> u8;
+	fn clear_interrupts();
+	fn tx_interrupt(enable: bool);
+}
+
+// The buffers behind a UART in interrupt driven mode.  The IRQ handler fills rx and empties tx.  Writers have to be serialized (by the console lock), and so do readers.
+pub struct SerialBuffers {
+	rx: RingBuffer<256>,
+	tx: RingBuffer<1024>,
+	enabled: AtomicBool,
+	// Whoever is moving bytes between the rings and the FIFOs.  This is what keeps tx single consumer when a writer has to empty it itself.
+	servicing: AtomicBool,
+}
+
+impl SerialBuffers {
+	const fn new() -> Self {
+		Self {
+			rx: RingBuffer::new(),
+			tx: RingBuffer::new(),
+			enabled: AtomicBool::new(false),
+			servicing: AtomicBool::new(false),
+		}
 	}
-	pub fn flush(&mut self) {
+	fn is_enabled(&self) -> bool {
+		self.enabled.load(Ordering::Acquire)
+	}
+
+	// Move received bytes into rx, and fill the transmit FIFO from tx.  The TX interrupt is only left on while there's something to send.
+	fn service<F: Fifo>(&self) {
 		loop {
-			let s = unsafe { ptr::read_volatile(AUX_MU_STAT_REG) };
-			if s & 0b1000 != 0 {
-				break;
+			if self.servicing.swap(true, Ordering::Acquire) {
+				return;
+			}
+			F::clear_interrupts();
+			while !F::rx_empty() {
+				// Bytes that don't fit are dropped
+				self.rx.push(F::get());
+			}
+			while !F::tx_full() {
+				match self.tx.pop() {
+					Some(b) => F::put(b),
+					None => break,
+				}
 			}
+			let sending = !self.tx.is_empty();
+			F::tx_interrupt(sending);
+			self.servicing.store(false, Ordering::Release);
+			// A writer might have added to tx after we looked, and then failed to get in
+			if sending || self.tx.is_empty() {
+				return;
+			}
+		}
+	}
+
+	fn write<F: Fifo>(&self, b: u8) {
+		// If tx is full then empty it ourselves, in case the IRQ can't run (because interrupts are masked)
+		while !self.tx.push(b) {
+			self.service::<F>();
+			spin_loop();
+		}
+		self.service::<F>();
+	}
+
+	fn drain<F: Fifo>(&self) {
+		while !self.tx.is_empty() {
+			self.service::<F>();
 			spin_loop();
 		}
 	}
 }
-impl Write for Uart1 {
-	fn write_str(&mut self, s: &str) -> fmt::Result {
-		let bytes = s.as_bytes();
-		let r = b"\r\n";
-
-		// Send out each byte, replacing \n with \r\n
-		for b in s
-			.char_indices()
-			.map(|(i, c)| match c {
-				'\n' => r,
-				_ => &bytes[i..i + c.len_utf8()],
-			})
-			.flatten()
-		{
-			while !self.transmit_ready() {
-				spin_loop();
+
+// The UART that the console (and the chainloader) use: the mini UART, or the PL011 with the pl011 feature.
+#[cfg(not(feature = "pl011"))]
+pub type ConsoleUart = Uart1;
+#[cfg(feature = "pl011")]
+pub type ConsoleUart = Uart0;
+
+// Take the console UART's pins and set it up at baud, 8N1
+#[cfg(all(target_arch = "aarch64", not(feature = "pl011")))]
+pub fn console_uart(baud: u32) -> Result<ConsoleUart, UartError> {
+	Uart1::with_config(Uart1Pins::take()?, baud, 8)
+}
+#[cfg(all(target_arch = "aarch64", feature = "pl011"))]
+pub fn console_uart(baud: u32) -> Result<ConsoleUart, UartError> {
+	Uart0::new(Uart0Pins::take(false)?, Config::new(baud))
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	struct Loopback {
+		input: Vec<u8>,
+		output: Vec<u8>,
+	}
+	impl SerialPort for Loopback {
+		fn write_byte(&mut self, b: u8) {
+			self.output.push(b);
+		}
+		fn try_read(&mut self) -> Option<u8> {
+			if self.input.is_empty() {
+				None
+			} else {
+				Some(self.input.remove(0))
 			}
-			self.queue_byte(*b);
 		}
-		self.flush();
-		Ok(())
+		fn flush(&mut self) {}
+	}
+	impl Write for Loopback {
+		fn write_str(&mut self, s: &str) -> fmt::Result {
+			write_serial(self, s)
+		}
+	}
+
+	#[test]
+	fn check_read_line() {
+		let mut port = Loopback {
+			input: b"hex\x7flp\x01!\rnext".to_vec(),
+			output: Vec::new(),
+		};
+		let mut buf = [0; 5];
+		assert_eq!(port.read_line(&mut buf), "help!");
+		assert_eq!(port.output, b"hex\x08 \x08lp!\r\n");
+		assert_eq!(port.input, b"next");
+
+		// Anything past the end of the buffer is dropped
+		port.input = b"abcdefg\n".to_vec();
+		assert_eq!(port.read_line(&mut buf), "abcde");
 	}
 }
@@ -0,0 +1,253 @@
+// The mini UART (UART1), in the AUX block.  Its baud rate comes from the core clock.
+use super::{check_pin, write_serial, Fifo, SerialBuffers, SerialPort, UartError};
+#[cfg(target_arch = "aarch64")]
+use crate::interrupts::{self, IrqSource};
+use crate::{
+	delay,
+	gpio::{Alt5, Gpio, Pin},
+	memory::uart::*,
+	set_bits,
+	sync::SpinLock,
+};
+use core::{
+	fmt::{self, Write},
+	hint::spin_loop,
+	ptr,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+// The mini UART runs off of the VPU core clock, which is 250MHz unless core_freq says otherwise.
+pub const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+// How far off the real baud rate can be, in tenths of a percent.  The receiver resyncs on every start bit, so it can only drift by about half a bit over a whole frame.
+const BAUD_TOLERANCE: u64 = 20;
+
+// baud = core_clock / (8 * (divisor + 1))
+pub fn mini_uart_divisor(core_clock: u32, baud: u32) -> Result<u16, UartError> {
+	let (clock, target) = (core_clock as u64, baud as u64);
+	let closest = |div: u64| (clock / (8 * div)) as u32;
+	if baud == 0 {
+		return Err(UartError::UnreachableBaud {
+			baud,
+			closest: closest(u16::MAX as u64 + 1),
+		});
+	}
+	let div = (clock + 4 * target) / (8 * target);
+	if div == 0 || div - 1 > u16::MAX as u64 {
+		return Err(UartError::UnreachableBaud {
+			baud,
+			closest: closest(div.clamp(1, u16::MAX as u64 + 1)),
+		});
+	}
+	let actual = closest(div) as u64;
+	if actual.max(target) - actual.min(target) > target * BAUD_TOLERANCE / 1000 {
+		return Err(UartError::UnreachableBaud {
+			baud,
+			closest: actual as u32,
+		});
+	}
+	Ok((div - 1) as u16)
+}
+
+// The mini UART's pins: TXD1 is GPIO 14 and RXD1 is GPIO 15
+pub struct Uart1Pins {
+	pub tx: Pin<Alt5>,
+	pub rx: Pin<Alt5>,
+}
+impl Uart1Pins {
+	#[track_caller]
+	pub fn take() -> Result<Self, UartError> {
+		// If rx is taken, tx gets dropped (and released) on the way out
+		let tx = Gpio::take(14).ok_or(UartError::PinTaken(14))?;
+		let rx = Gpio::take(15).ok_or(UartError::PinTaken(15))?;
+		Ok(Self {
+			tx: tx.into_alt(),
+			rx: rx.into_alt(),
+		})
+	}
+}
+
+static UART1_BUFFERS: SerialBuffers = SerialBuffers::new();
+static UART1_READY: AtomicBool = AtomicBool::new(false);
+// The UART holds onto its pins until it's released
+static UART1_PINS: SpinLock<Option<Uart1Pins>> = SpinLock::new(None);
+
+pub struct Uart1;
+impl Uart1 {
+	// Only the first call sets the UART up (at 115200 baud, assuming the default core clock), so that grabbing it again doesn't undo with_config.  None if something else has the pins.  This is what the panic handler gets, so it can't panic: the pins might be taken by a with_config that hasn't finished, or by the PL011.
+	#[track_caller]
+	pub fn try_new() -> Option<Self> {
+		if UART1_READY.load(Ordering::Acquire) {
+			return Some(Self {});
+		}
+		let pins = Uart1Pins::take().ok()?;
+		Self::with_clock(pins, DEFAULT_CORE_CLOCK, 115200, 8).ok()
+	}
+
+	// Ask the firmware what the core clock is
+	#[cfg(target_arch = "aarch64")]
+	pub fn with_config(pins: Uart1Pins, baud: u32, data_bits: u8) -> Result<Self, UartError> {
+		use crate::mailbox::{self, Clock, GetClockRate};
+		let core_clock = mailbox::request(GetClockRate(Clock::Core)).unwrap_or(DEFAULT_CORE_CLOCK);
+		Self::with_clock(pins, core_clock, baud, data_bits)
+	}
+
+	// data_bits can be 7 or 8
+	pub fn with_clock(
+		pins: Uart1Pins,
+		core_clock: u32,
+		baud: u32,
+		data_bits: u8,
+	) -> Result<Self, UartError> {
+		check_pin(&pins.tx, 14)?;
+		check_pin(&pins.rx, 15)?;
+		let divisor = mini_uart_divisor(core_clock, baud)?;
+		let lcr = match data_bits {
+			7 => 0b00,
+			// The datasheet says 8 bit is 0b01, but it's actually 0b11
+			8 => 0b11,
+			_ => return Err(UartError::DataBits(data_bits)),
+		};
+		*UART1_PINS.lock() = Some(pins);
+		unsafe {
+			// Turn the mini uart off while we change it
+			*AUX_MU_CNTL_REG = 0;
+			set_bits(AUX_MU_BAUD, 0..16, divisor as u32);
+			*AUX_MU_LCR_REG = lcr;
+			// Give a little delay so that the aux can take effect? I guess?
+			delay(150);
+			// Enable the mini uart
+			*AUX_MU_CNTL_REG = 0b_0_0_00_0_0_1_1;
+		}
+		UART1_READY.store(true, Ordering::Release);
+		Ok(Self {})
+	}
+	// Turn the mini UART off and give its pins back (so that the PL011 can have them, say).  Nothing switches UARTs yet.
+	#[allow(dead_code)]
+	pub fn release(self) -> Option<Uart1Pins> {
+		unsafe { *AUX_MU_CNTL_REG = 0 };
+		UART1_READY.store(false, Ordering::Release);
+		UART1_PINS.lock().take()
+	}
+	fn transmit_ready(&self) -> bool {
+		let s = unsafe { ptr::read_volatile(AUX_MU_STAT_REG) };
+		s & 0b10 != 0
+	}
+	// If queue_byte is called when the transmit queue is full, the byte will be lost.
+	fn queue_byte(&mut self, b: u8) {
+		unsafe { ptr::write_volatile(AUX_MU_IO_REG, b as u32) };
+	}
+
+	// Switch to interrupt driven mode: received bytes are buffered by the IRQ handler, and writes only block if the transmit buffer is full.
+	#[cfg(target_arch = "aarch64")]
+	pub fn enable_interrupts(&mut self) {
+		UART1_BUFFERS.enabled.store(true, Ordering::Release);
+		interrupts::register_handler(IrqSource::Gpu(AUX_IRQ), || UART1_BUFFERS.service::<Uart1>());
+		unsafe { ptr::write_volatile(AUX_MU_IER_REG, AUX_MU_IER_RX) };
+	}
+}
+impl Fifo for Uart1 {
+	fn tx_full() -> bool {
+		let s = unsafe { ptr::read_volatile(AUX_MU_STAT_REG) };
+		s & 0b10 == 0
+	}
+	fn rx_empty() -> bool {
+		let s = unsafe { ptr::read_volatile(AUX_MU_STAT_REG) };
+		s & 0b1 == 0
+	}
+	fn put(b: u8) {
+		unsafe { ptr::write_volatile(AUX_MU_IO_REG, b as u32) };
+	}
+	fn get() -> u8 {
+		unsafe { ptr::read_volatile(AUX_MU_IO_REG) as u8 }
+	}
+	// The mini UART's interrupts go away on their own once the receive FIFO is empty / the transmit FIFO isn't
+	fn clear_interrupts() {}
+	fn tx_interrupt(enable: bool) {
+		let ier = if enable {
+			AUX_MU_IER_RX | AUX_MU_IER_TX
+		} else {
+			AUX_MU_IER_RX
+		};
+		unsafe { ptr::write_volatile(AUX_MU_IER_REG, ier) };
+	}
+}
+impl SerialPort for Uart1 {
+	fn write_byte(&mut self, b: u8) {
+		if UART1_BUFFERS.is_enabled() {
+			UART1_BUFFERS.write::<Uart1>(b);
+			return;
+		}
+		while !self.transmit_ready() {
+			spin_loop();
+		}
+		self.queue_byte(b);
+	}
+	fn try_read(&mut self) -> Option<u8> {
+		if UART1_BUFFERS.is_enabled() {
+			UART1_BUFFERS.rx.pop()
+		} else if !Self::rx_empty() {
+			Some(Self::get())
+		} else {
+			None
+		}
+	}
+	fn flush(&mut self) {
+		if UART1_BUFFERS.is_enabled() {
+			UART1_BUFFERS.drain::<Uart1>();
+		}
+		loop {
+			let s = unsafe { ptr::read_volatile(AUX_MU_STAT_REG) };
+			if s & 0b1000 != 0 {
+				break;
+			}
+			spin_loop();
+		}
+	}
+}
+impl Write for Uart1 {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		write_serial(self, s)
+	}
+}
+
+// The bits of AUX_MU_IER_REG.  The datasheet has the RX and TX bits swapped, and bits 2 and 3 have to be set for the interrupts to happen at all.
+const AUX_MU_IER_RX: u32 = 0b1101;
+const AUX_MU_IER_TX: u32 = 0b0010;
+const AUX_IRQ: u8 = 29;
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_mini_uart_divisor() {
+		assert_eq!(mini_uart_divisor(250_000_000, 115200), Ok(270));
+		assert_eq!(mini_uart_divisor(400_000_000, 115200), Ok(433));
+		assert_eq!(mini_uart_divisor(250_000_000, 9600), Ok(3254));
+		// 3.125MHz is 4% fast
+		assert_eq!(
+			mini_uart_divisor(250_000_000, 3_000_000),
+			Err(UartError::UnreachableBaud {
+				baud: 3_000_000,
+				closest: 3_125_000
+			})
+		);
+		// Too slow for a 16 bit divisor
+		assert_eq!(
+			mini_uart_divisor(250_000_000, 300),
+			Err(UartError::UnreachableBaud {
+				baud: 300,
+				closest: 476
+			})
+		);
+		// Too fast for any divisor
+		assert!(mini_uart_divisor(250_000_000, 100_000_000).is_err());
+		assert_eq!(
+			mini_uart_divisor(250_000_000, 0),
+			Err(UartError::UnreachableBaud {
+				baud: 0,
+				closest: 476
+			})
+		);
+	}
+}
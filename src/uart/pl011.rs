@@ -0,0 +1,348 @@
+// The PL011 (UART0).  It has its own clock (UARTCLK), a fractional baud divisor, and flow control.
+use super::{check_pin, write_serial, Fifo, SerialBuffers, SerialPort, UartError};
+#[cfg(target_arch = "aarch64")]
+use crate::interrupts::{self, IrqSource};
+use crate::{
+	gpio::{Alt0, Alt3, Gpio, Pin},
+	memory::uart::*,
+	sync::SpinLock,
+};
+use core::{
+	fmt::{self, Write},
+	hint::spin_loop,
+	ptr,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+// The console is 8N1, so only the tests use the others
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parity {
+	None,
+	Odd,
+	Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopBits {
+	One,
+	Two,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+	pub baud: u32,
+	// 5 to 8
+	pub data_bits: u8,
+	pub parity: Parity,
+	pub stop_bits: StopBits,
+	// RTS / CTS on GPIO 17 / 16
+	pub flow_control: bool,
+}
+impl Config {
+	// 8N1 without flow control
+	pub const fn new(baud: u32) -> Self {
+		Self {
+			baud,
+			data_bits: 8,
+			parity: Parity::None,
+			stop_bits: StopBits::One,
+			flow_control: false,
+		}
+	}
+}
+impl Default for Config {
+	fn default() -> Self {
+		Self::new(115200)
+	}
+}
+
+const UART0_IRQ: u8 = 57;
+
+// What the firmware sets UARTCLK to, unless init_uart_clock is in config.txt
+pub const UART0_DEFAULT_CLOCK: u32 = 48_000_000;
+
+const FR_BUSY: u32 = 1 << 3;
+const FR_RXFE: u32 = 1 << 4;
+const FR_TXFF: u32 = 1 << 5;
+const LCRH_PEN: u32 = 1 << 1;
+const LCRH_EPS: u32 = 1 << 2;
+const LCRH_STP2: u32 = 1 << 3;
+const LCRH_FEN: u32 = 1 << 4;
+const CR_UARTEN: u32 = 1 << 0;
+const CR_TXE: u32 = 1 << 8;
+const CR_RXE: u32 = 1 << 9;
+const CR_RTSEN: u32 = 1 << 14;
+const CR_CTSEN: u32 = 1 << 15;
+// The interrupt bits are the same in IMSC, MIS, and ICR.  RT is the receive timeout, for when the FIFO has something in it but isn't past the trigger level.
+const IMSC_RX: u32 = 1 << 4;
+const IMSC_TX: u32 = 1 << 5;
+const IMSC_RT: u32 = 1 << 6;
+
+// The PL011 divides UARTCLK by 16 * baud, with the integer part in IBRD and the fraction in 64ths in FBRD.  IBRD is 16 bits and can't be 0.
+pub fn pl011_divisor(uartclk: u32, baud: u32) -> Result<(u32, u32), UartError> {
+	const MIN_DIV: u64 = 1 << 6;
+	const MAX_DIV: u64 = 0xFFFF << 6;
+	let closest = |div: u64| ((uartclk as u64 * 4 + div / 2) / div) as u32;
+	if baud == 0 {
+		return Err(UartError::UnreachableBaud {
+			baud,
+			closest: closest(MAX_DIV),
+		});
+	}
+	let baud = baud as u64;
+	// uartclk / (16 * baud) * 64, rounded to the nearest 64th
+	let div = (uartclk as u64 * 4 + baud / 2) / baud;
+	if !(MIN_DIV..=MAX_DIV).contains(&div) {
+		return Err(UartError::UnreachableBaud {
+			baud: baud as u32,
+			closest: closest(div.clamp(MIN_DIV, MAX_DIV)),
+		});
+	}
+	Ok(((div >> 6) as u32, (div & 0x3F) as u32))
+}
+
+fn line_control(config: &Config) -> Result<u32, UartError> {
+	if !(5..=8).contains(&config.data_bits) {
+		return Err(UartError::DataBits(config.data_bits));
+	}
+	let mut lcrh = ((config.data_bits as u32 - 5) << 5) | LCRH_FEN;
+	match config.parity {
+		Parity::None => {}
+		Parity::Odd => lcrh |= LCRH_PEN,
+		Parity::Even => lcrh |= LCRH_PEN | LCRH_EPS,
+	}
+	if config.stop_bits == StopBits::Two {
+		lcrh |= LCRH_STP2;
+	}
+	Ok(lcrh)
+}
+
+// The PL011's pins: TXD0 / RXD0 are GPIO 14 / 15, and CTS0 / RTS0 are GPIO 16 / 17
+pub struct Uart0Pins {
+	pub tx: Pin<Alt0>,
+	pub rx: Pin<Alt0>,
+	// Only needed for flow control
+	pub cts_rts: Option<(Pin<Alt3>, Pin<Alt3>)>,
+}
+impl Uart0Pins {
+	#[track_caller]
+	pub fn take(flow_control: bool) -> Result<Self, UartError> {
+		let tx = Gpio::take(14).ok_or(UartError::PinTaken(14))?;
+		let rx = Gpio::take(15).ok_or(UartError::PinTaken(15))?;
+		let cts_rts = if flow_control {
+			let cts = Gpio::take(16).ok_or(UartError::PinTaken(16))?;
+			let rts = Gpio::take(17).ok_or(UartError::PinTaken(17))?;
+			Some((cts.into_alt(), rts.into_alt()))
+		} else {
+			None
+		};
+		Ok(Self {
+			tx: tx.into_alt(),
+			rx: rx.into_alt(),
+			cts_rts,
+		})
+	}
+}
+
+static UART0_BUFFERS: SerialBuffers = SerialBuffers::new();
+static UART0_READY: AtomicBool = AtomicBool::new(false);
+static UART0_PINS: SpinLock<Option<Uart0Pins>> = SpinLock::new(None);
+
+// The PL011.  It uses the same pins as the mini UART (14 / 15), so only one of them can be connected at a time: whichever has the pins.
+pub struct Uart0;
+impl Uart0 {
+	// Ask the firmware what UARTCLK is
+	#[cfg(target_arch = "aarch64")]
+	pub fn new(pins: Uart0Pins, config: Config) -> Result<Self, UartError> {
+		use crate::mailbox::{self, Clock, GetClockRate};
+		let uartclk = mailbox::request(GetClockRate(Clock::Uart)).unwrap_or(UART0_DEFAULT_CLOCK);
+		Self::with_clock(pins, uartclk, config)
+	}
+	// Same as Uart1::try_new, at 115200 8N1 and the default UARTCLK
+	#[track_caller]
+	pub fn try_new() -> Option<Self> {
+		if UART0_READY.load(Ordering::Acquire) {
+			return Some(Self);
+		}
+		let pins = Uart0Pins::take(false).ok()?;
+		Self::with_clock(pins, UART0_DEFAULT_CLOCK, Config::new(115200)).ok()
+	}
+	pub fn with_clock(pins: Uart0Pins, uartclk: u32, config: Config) -> Result<Self, UartError> {
+		check_pin(&pins.tx, 14)?;
+		check_pin(&pins.rx, 15)?;
+		match (&pins.cts_rts, config.flow_control) {
+			(Some((cts, rts)), _) => {
+				check_pin(cts, 16)?;
+				check_pin(rts, 17)?;
+			}
+			(None, true) => return Err(UartError::NoFlowControlPins),
+			(None, false) => {}
+		}
+		let (ibrd, fbrd) = pl011_divisor(uartclk, config.baud)?;
+		let lcrh = line_control(&config)?;
+		unsafe {
+			// The UART has to be off (and finished sending) while it's configured
+			ptr::write_volatile(UART0_CR, 0);
+			while ptr::read_volatile(UART0_FR) & FR_BUSY != 0 {
+				spin_loop();
+			}
+			// Turning the FIFOs off flushes them
+			ptr::write_volatile(UART0_LCRH, 0);
+		}
+
+		*UART0_PINS.lock() = Some(pins);
+
+		let mut cr = CR_UARTEN | CR_TXE | CR_RXE;
+		if config.flow_control {
+			cr |= CR_RTSEN | CR_CTSEN;
+		}
+		unsafe {
+			// Mask and clear every interrupt
+			ptr::write_volatile(UART0_IMSC, 0);
+			ptr::write_volatile(UART0_ICR, 0x7FF);
+			ptr::write_volatile(UART0_IBRD, ibrd);
+			ptr::write_volatile(UART0_FBRD, fbrd);
+			// The divisors only take effect on a write to LCRH
+			ptr::write_volatile(UART0_LCRH, lcrh);
+			ptr::write_volatile(UART0_CR, cr);
+		}
+		UART0_READY.store(true, Ordering::Release);
+		Ok(Self)
+	}
+	// Turn the PL011 off and give its pins back (so that the mini UART can have them)
+	#[allow(dead_code)]
+	pub fn release(self) -> Option<Uart0Pins> {
+		unsafe {
+			ptr::write_volatile(UART0_IMSC, 0);
+			ptr::write_volatile(UART0_CR, 0);
+		}
+		UART0_READY.store(false, Ordering::Release);
+		UART0_PINS.lock().take()
+	}
+
+	// Same as Uart1::enable_interrupts
+	#[cfg(target_arch = "aarch64")]
+	pub fn enable_interrupts(&mut self) {
+		UART0_BUFFERS.enabled.store(true, Ordering::Release);
+		interrupts::register_handler(IrqSource::Gpu(UART0_IRQ), || {
+			UART0_BUFFERS.service::<Uart0>()
+		});
+		unsafe { ptr::write_volatile(UART0_IMSC, IMSC_RX | IMSC_RT) };
+	}
+}
+impl Fifo for Uart0 {
+	fn tx_full() -> bool {
+		let s = unsafe { ptr::read_volatile(UART0_FR) };
+		s & FR_TXFF != 0
+	}
+	fn rx_empty() -> bool {
+		let s = unsafe { ptr::read_volatile(UART0_FR) };
+		s & FR_RXFE != 0
+	}
+	fn put(b: u8) {
+		unsafe { ptr::write_volatile(UART0_DR, b as u32) };
+	}
+	fn get() -> u8 {
+		// The top bits are error flags
+		unsafe { ptr::read_volatile(UART0_DR) as u8 }
+	}
+	fn clear_interrupts() {
+		unsafe { ptr::write_volatile(UART0_ICR, IMSC_RX | IMSC_TX | IMSC_RT) };
+	}
+	// The PL011 only raises TX when the FIFO drains past its trigger level, so the FIFO has to be filled (by service) before this is turned on.
+	fn tx_interrupt(enable: bool) {
+		let imsc = if enable {
+			IMSC_RX | IMSC_RT | IMSC_TX
+		} else {
+			IMSC_RX | IMSC_RT
+		};
+		unsafe { ptr::write_volatile(UART0_IMSC, imsc) };
+	}
+}
+impl SerialPort for Uart0 {
+	fn write_byte(&mut self, b: u8) {
+		if UART0_BUFFERS.is_enabled() {
+			UART0_BUFFERS.write::<Uart0>(b);
+			return;
+		}
+		while Self::tx_full() {
+			spin_loop();
+		}
+		Self::put(b);
+	}
+	fn try_read(&mut self) -> Option<u8> {
+		if UART0_BUFFERS.is_enabled() {
+			UART0_BUFFERS.rx.pop()
+		} else if !Self::rx_empty() {
+			Some(Self::get())
+		} else {
+			None
+		}
+	}
+	fn flush(&mut self) {
+		if UART0_BUFFERS.is_enabled() {
+			UART0_BUFFERS.drain::<Uart0>();
+		}
+		while unsafe { ptr::read_volatile(UART0_FR) } & FR_BUSY != 0 {
+			spin_loop();
+		}
+	}
+}
+impl Write for Uart0 {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		write_serial(self, s)
+	}
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_pl011_divisor() {
+		assert_eq!(pl011_divisor(48_000_000, 115200), Ok((26, 3)));
+		assert_eq!(pl011_divisor(3_000_000, 115200), Ok((1, 40)));
+		assert_eq!(pl011_divisor(48_000_000, 9600), Ok((312, 32)));
+		// IBRD would be 0
+		assert_eq!(
+			pl011_divisor(3_000_000, 250_000),
+			Err(UartError::UnreachableBaud {
+				baud: 250_000,
+				closest: 187_500
+			})
+		);
+		// IBRD would be more than 16 bits
+		assert_eq!(
+			pl011_divisor(48_000_000, 40),
+			Err(UartError::UnreachableBaud {
+				baud: 40,
+				closest: 46
+			})
+		);
+		assert!(pl011_divisor(48_000_000, 0).is_err());
+	}
+
+	#[test]
+	fn check_line_control() {
+		assert_eq!(line_control(&Config::default()), Ok(0b111_0000));
+		let config = Config {
+			data_bits: 7,
+			parity: Parity::Even,
+			stop_bits: StopBits::Two,
+			..Config::default()
+		};
+		assert_eq!(line_control(&config), Ok(0b101_1110));
+		let config = Config {
+			data_bits: 5,
+			parity: Parity::Odd,
+			..Config::default()
+		};
+		assert_eq!(line_control(&config), Ok(0b001_0010));
+		let config = Config {
+			data_bits: 9,
+			..Config::default()
+		};
+		assert_eq!(line_control(&config), Err(UartError::DataBits(9)));
+	}
+}
@@ -0,0 +1,1254 @@
+# Generates src/font.psf, the framebuffer console's font: `python3 tools/mkfont.py` (optionally followed by characters to print, to check how they look).
+# The glyphs are drawn by hand for this project, they aren't taken from any other font.
+# Hand drawn 8x16 glyphs. Each glyph is 12 rows (cell rows 3..14), 7 columns (column 7 is always blank).
+# Caps: rows 3-12, lowercase x-height: rows 6-12, descenders: rows 13-14.
+G = {}
+def g(c, art):
+    rows = [r for r in art.strip('\n').split('\n')]
+    assert len(rows) == 12, (c, len(rows))
+    for r in rows: assert len(r) == 7, (c, r)
+    G[c] = rows
+B = '.......'
+g(' ', '\n'.join([B]*12))
+g('!', """
+...#...
+..###..
+..###..
+..###..
+...#...
+...#...
+...#...
+.......
+...#...
+...#...
+.......
+.......""")
+g('"', """
+.##.##.
+.##.##.
+.#..#..
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......""")
+g('#', """
+.......
+.##.##.
+.##.##.
+#######
+.##.##.
+.##.##.
+.##.##.
+#######
+.##.##.
+.##.##.
+.......
+.......""")
+g('$', """
+...#...
+.#####.
+##...##
+##.....
+##.....
+.#####.
+.....##
+.....##
+##...##
+.#####.
+...#...
+...#...""")
+g('%', """
+.......
+.......
+##....#
+##...##
+....##.
+...##..
+..##...
+.##....
+##...##
+#....##
+.......
+.......""")
+g('&', """
+..###..
+.##.##.
+.##.##.
+..###..
+.###.##
+##.###.
+##..##.
+##..##.
+##..##.
+.###.##
+.......
+.......""")
+g("'", """
+..##...
+..##...
+.##....
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......""")
+g('(', """
+....##.
+...##..
+..##...
+..##...
+..##...
+..##...
+..##...
+..##...
+...##..
+....##.
+.......
+.......""")
+g(')', """
+.##....
+..##...
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+..##...
+.##....
+.......
+.......""")
+g('*', """
+.......
+.......
+.......
+.##.##.
+..###..
+#######
+..###..
+.##.##.
+.......
+.......
+.......
+.......""")
+g('+', """
+.......
+.......
+.......
+...#...
+...#...
+.#####.
+...#...
+...#...
+.......
+.......
+.......
+.......""")
+g(',', """
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+..##...
+..##...
+..##...
+.##....""")
+g('-', """
+.......
+.......
+.......
+.......
+.......
+#######
+.......
+.......
+.......
+.......
+.......
+.......""")
+g('.', """
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+..##...
+..##...
+.......
+.......""")
+g('/', """
+.......
+......#
+.....##
+....##.
+...##..
+..##...
+.##....
+##.....
+#......
+.......
+.......
+.......""")
+g('0', """
+..###..
+.##.##.
+##...##
+##..###
+##.####
+####.##
+###..##
+##...##
+.##.##.
+..###..
+.......
+.......""")
+g('1', """
+...##..
+..###..
+.####..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+.######
+.......
+.......""")
+g('2', """
+.#####.
+##...##
+.....##
+....##.
+...##..
+..##...
+.##....
+##.....
+##...##
+#######
+.......
+.......""")
+g('3', """
+.#####.
+##...##
+.....##
+.....##
+..####.
+.....##
+.....##
+.....##
+##...##
+.#####.
+.......
+.......""")
+g('4', """
+....##.
+...###.
+..####.
+.##.##.
+##..##.
+#######
+....##.
+....##.
+....##.
+...####
+.......
+.......""")
+g('5', """
+#######
+##.....
+##.....
+##.....
+######.
+.....##
+.....##
+.....##
+##...##
+.#####.
+.......
+.......""")
+g('6', """
+..###..
+.##....
+##.....
+##.....
+######.
+##...##
+##...##
+##...##
+##...##
+.#####.
+.......
+.......""")
+g('7', """
+#######
+##...##
+.....##
+....##.
+...##..
+..##...
+..##...
+..##...
+..##...
+..##...
+.......
+.......""")
+g('8', """
+.#####.
+##...##
+##...##
+##...##
+.#####.
+##...##
+##...##
+##...##
+##...##
+.#####.
+.......
+.......""")
+g('9', """
+.#####.
+##...##
+##...##
+##...##
+.######
+.....##
+.....##
+.....##
+....##.
+.####..
+.......
+.......""")
+g(':', """
+.......
+.......
+.......
+..##...
+..##...
+.......
+.......
+.......
+..##...
+..##...
+.......
+.......""")
+g(';', """
+.......
+.......
+.......
+..##...
+..##...
+.......
+.......
+.......
+..##...
+..##...
+.##....
+.......""")
+g('<', """
+.......
+.....##
+....##.
+...##..
+..##...
+.##....
+..##...
+...##..
+....##.
+.....##
+.......
+.......""")
+g('=', """
+.......
+.......
+.......
+.......
+.######
+.......
+.......
+.######
+.......
+.......
+.......
+.......""")
+g('>', """
+.......
+.##....
+..##...
+...##..
+....##.
+.....##
+....##.
+...##..
+..##...
+.##....
+.......
+.......""")
+g('?', """
+.#####.
+##...##
+##...##
+....##.
+...##..
+...##..
+...##..
+.......
+...##..
+...##..
+.......
+.......""")
+g('@', """
+.......
+.#####.
+##...##
+##...##
+##.####
+##.####
+##.####
+##.###.
+##.....
+.#####.
+.......
+.......""")
+g('A', """
+...#...
+..###..
+.##.##.
+##...##
+##...##
+#######
+##...##
+##...##
+##...##
+##...##
+.......
+.......""")
+g('B', """
+######.
+.##..##
+.##..##
+.##..##
+.#####.
+.##..##
+.##..##
+.##..##
+.##..##
+######.
+.......
+.......""")
+g('C', """
+..####.
+.##..##
+##....#
+##.....
+##.....
+##.....
+##.....
+##....#
+.##..##
+..####.
+.......
+.......""")
+g('D', """
+#####..
+.##.##.
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+.##.##.
+#####..
+.......
+.......""")
+g('E', """
+#######
+.##..##
+.##...#
+.##.#..
+.####..
+.##.#..
+.##....
+.##...#
+.##..##
+#######
+.......
+.......""")
+g('F', """
+#######
+.##..##
+.##...#
+.##.#..
+.####..
+.##.#..
+.##....
+.##....
+.##....
+####...
+.......
+.......""")
+g('G', """
+..####.
+.##..##
+##....#
+##.....
+##.....
+##.####
+##...##
+##...##
+.##..##
+..###.#
+.......
+.......""")
+g('H', """
+##...##
+##...##
+##...##
+##...##
+#######
+##...##
+##...##
+##...##
+##...##
+##...##
+.......
+.......""")
+g('I', """
+.####..
+..##...
+..##...
+..##...
+..##...
+..##...
+..##...
+..##...
+..##...
+.####..
+.......
+.......""")
+g('J', """
+...####
+....##.
+....##.
+....##.
+....##.
+....##.
+##..##.
+##..##.
+##..##.
+.####..
+.......
+.......""")
+g('K', """
+###..##
+.##..##
+.##.##.
+.##.##.
+.####..
+.####..
+.##.##.
+.##..##
+.##..##
+###..##
+.......
+.......""")
+g('L', """
+####...
+.##....
+.##....
+.##....
+.##....
+.##....
+.##....
+.##...#
+.##..##
+#######
+.......
+.......""")
+g('M', """
+##...##
+###.###
+#######
+#######
+##.#.##
+##...##
+##...##
+##...##
+##...##
+##...##
+.......
+.......""")
+g('N', """
+##...##
+###..##
+####.##
+#######
+##.####
+##..###
+##...##
+##...##
+##...##
+##...##
+.......
+.......""")
+g('O', """
+.#####.
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+.#####.
+.......
+.......""")
+g('P', """
+######.
+.##..##
+.##..##
+.##..##
+.#####.
+.##....
+.##....
+.##....
+.##....
+####...
+.......
+.......""")
+g('Q', """
+.#####.
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##.#.##
+##.####
+.#####.
+....##.
+....###""")
+g('R', """
+######.
+.##..##
+.##..##
+.##..##
+.#####.
+.##.##.
+.##..##
+.##..##
+.##..##
+###..##
+.......
+.......""")
+g('S', """
+.#####.
+##...##
+##...##
+.##....
+..###..
+....##.
+.....##
+##...##
+##...##
+.#####.
+.......
+.......""")
+g('T', """
+######.
+######.
+#.##.#.
+..##...
+..##...
+..##...
+..##...
+..##...
+..##...
+.####..
+.......
+.......""")
+g('U', """
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+.#####.
+.......
+.......""")
+g('V', """
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+##...##
+.##.##.
+..###..
+...#...
+.......
+.......""")
+g('W', """
+##...##
+##...##
+##...##
+##...##
+##.#.##
+##.#.##
+##.#.##
+#######
+###.###
+.##.##.
+.......
+.......""")
+g('X', """
+##...##
+##...##
+.##.##.
+.#####.
+..###..
+..###..
+.#####.
+.##.##.
+##...##
+##...##
+.......
+.......""")
+g('Y', """
+##..##.
+##..##.
+##..##.
+##..##.
+.####..
+..##...
+..##...
+..##...
+..##...
+.####..
+.......
+.......""")
+g('Z', """
+#######
+##...##
+#...##.
+....##.
+...##..
+..##...
+.##....
+##....#
+##...##
+#######
+.......
+.......""")
+g('[', """
+.####..
+.##....
+.##....
+.##....
+.##....
+.##....
+.##....
+.##....
+.##....
+.####..
+.......
+.......""")
+g('\\', """
+.......
+#......
+##.....
+.##....
+..##...
+...##..
+....##.
+.....##
+......#
+.......
+.......
+.......""")
+g(']', """
+.####..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+.####..
+.......
+.......""")
+g('^', """
+...#...
+..###..
+.##.##.
+##...##
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......""")
+g('_', """
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+#######""")
+g('`', """
+.##....
+..##...
+...##..
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......""")
+g('a', """
+.......
+.......
+.......
+.####..
+....##.
+.#####.
+##..##.
+##..##.
+##..##.
+.###.##
+.......
+.......""")
+g('b', """
+###....
+.##....
+.##....
+.####..
+.##.##.
+.##..##
+.##..##
+.##..##
+.##..##
+.#####.
+.......
+.......""")
+g('c', """
+.......
+.......
+.......
+.#####.
+##...##
+##.....
+##.....
+##.....
+##...##
+.#####.
+.......
+.......""")
+g('d', """
+...###.
+....##.
+....##.
+..####.
+.##.##.
+##..##.
+##..##.
+##..##.
+##..##.
+.###.##
+.......
+.......""")
+g('e', """
+.......
+.......
+.......
+.#####.
+##...##
+#######
+##.....
+##.....
+##...##
+.#####.
+.......
+.......""")
+g('f', """
+..###..
+.##.##.
+.##..#.
+.##....
+####...
+.##....
+.##....
+.##....
+.##....
+####...
+.......
+.......""")
+g('g', """
+.......
+.......
+.......
+.###.##
+##..##.
+##..##.
+##..##.
+##..##.
+.#####.
+....##.
+##..##.
+.####..""")
+g('h', """
+###....
+.##....
+.##....
+.##.##.
+.###.##
+.##..##
+.##..##
+.##..##
+.##..##
+###..##
+.......
+.......""")
+g('i', """
+..##...
+..##...
+.......
+.###...
+..##...
+..##...
+..##...
+..##...
+..##...
+.####..
+.......
+.......""")
+g('j', """
+....##.
+....##.
+.......
+...###.
+....##.
+....##.
+....##.
+....##.
+....##.
+##..##.
+##..##.
+.####..""")
+g('k', """
+###....
+.##....
+.##....
+.##..##
+.##.##.
+.####..
+.####..
+.##.##.
+.##..##
+###..##
+.......
+.......""")
+g('l', """
+.###...
+..##...
+..##...
+..##...
+..##...
+..##...
+..##...
+..##...
+..##...
+.####..
+.......
+.......""")
+g('m', """
+.......
+.......
+.......
+###.##.
+#######
+##.#.##
+##.#.##
+##.#.##
+##.#.##
+##...##
+.......
+.......""")
+g('n', """
+.......
+.......
+.......
+##.###.
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+.##..##
+.......
+.......""")
+g('o', """
+.......
+.......
+.......
+.#####.
+##...##
+##...##
+##...##
+##...##
+##...##
+.#####.
+.......
+.......""")
+g('p', """
+.......
+.......
+.......
+##.###.
+.##..##
+.##..##
+.##..##
+.##..##
+.#####.
+.##....
+.##....
+####...""")
+g('q', """
+.......
+.......
+.......
+.###.##
+##..##.
+##..##.
+##..##.
+##..##.
+.#####.
+....##.
+....##.
+...####""")
+g('r', """
+.......
+.......
+.......
+##.###.
+.###.##
+.##..##
+.##....
+.##....
+.##....
+####...
+.......
+.......""")
+g('s', """
+.......
+.......
+.......
+.#####.
+##...##
+.##....
+..###..
+....##.
+##...##
+.#####.
+.......
+.......""")
+g('t', """
+...#...
+..##...
+..##...
+######.
+..##...
+..##...
+..##...
+..##...
+..##.##
+...###.
+.......
+.......""")
+g('u', """
+.......
+.......
+.......
+##..##.
+##..##.
+##..##.
+##..##.
+##..##.
+##..##.
+.###.##
+.......
+.......""")
+g('v', """
+.......
+.......
+.......
+##...##
+##...##
+##...##
+##...##
+.##.##.
+..###..
+...#...
+.......
+.......""")
+g('w', """
+.......
+.......
+.......
+##...##
+##...##
+##.#.##
+##.#.##
+##.#.##
+#######
+.##.##.
+.......
+.......""")
+g('x', """
+.......
+.......
+.......
+##...##
+.##.##.
+..###..
+...#...
+..###..
+.##.##.
+##...##
+.......
+.......""")
+g('y', """
+.......
+.......
+.......
+##...##
+##...##
+##...##
+##...##
+##...##
+.######
+.....##
+....##.
+#####..""")
+g('z', """
+.......
+.......
+.......
+#######
+##..##.
+...##..
+..##...
+.##....
+##...##
+#######
+.......
+.......""")
+g('{', """
+....###
+...##..
+...##..
+...##..
+.###...
+...##..
+...##..
+...##..
+...##..
+....###
+.......
+.......""")
+g('|', """
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+...##..
+.......""")
+g('}', """
+###....
+..##...
+..##...
+..##...
+...###.
+..##...
+..##...
+..##...
+..##...
+###....
+.......
+.......""")
+g('~', """
+.###.##
+##.###.
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......
+.......""")
+
+assert len(G) == 95, len(G)
+glyphs = [[0] * 16 for _ in range(256)]
+for c, rows in G.items():
+    for i, r in enumerate(rows):
+        glyphs[ord(c)][3 + i] = int(r.replace('#', '1').replace('.', '0') + '0', 2)
+# Anything we don't have a glyph for (other than space) draws as a hollow box
+for c in list(range(0x00, 0x20)) + list(range(0x7f, 0x100)):
+    box = [0] * 16
+    for r in range(3, 13): box[r] = 0b10000010
+    box[3] = box[12] = 0b11111110
+    glyphs[c] = box
+glyphs[0] = [0] * 16
+out = bytes([0x36, 0x04, 0x00, 16]) + bytes(b for gl in glyphs for b in gl)
+import os
+open(os.path.join(os.path.dirname(__file__), '..', 'src', 'font.psf'), 'wb').write(out)
+import sys
+for c in sys.argv[1:]:
+    for row in glyphs[ord(c)]:
+        print(''.join('#' if row & (0x80 >> b) else '.' for b in range(8)))